@@ -1,84 +1,202 @@
-use std::fs::OpenOptions;
-use std::io::{Read, Write};
+use std::sync::Arc;
 
-use base64::Engine;
-use base64::prelude::BASE64_URL_SAFE;
-use cpal::Stream;
-use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
-use crossterm::event::{Event, EventStream, KeyCode, KeyEvent, KeyEventKind};
-use futures::{FutureExt, StreamExt};
-use ratatui::{
-    DefaultTerminal, Frame,
-    layout::{Constraint, Layout},
-    widgets::ListState,
-};
+use anyhow::Context;
+use cpal::traits::{DeviceTrait, HostTrait};
+use serde::{Deserialize, Serialize};
 use tokio::select;
+use tokio::sync::{mpsc, watch};
+use webrtc::ice_transport::ice_connection_state::RTCIceConnectionState;
 use webrtc::ice_transport::ice_server::RTCIceServer;
 use webrtc::peer_connection::configuration::RTCConfiguration;
 use webrtc::peer_connection::peer_connection_state::RTCPeerConnectionState;
+use webrtc::peer_connection::sdp::sdp_type::RTCSdpType;
 use webrtc::peer_connection::sdp::session_description::RTCSessionDescription;
 
 pub struct Device {
-    pub cpal_device: cpal::Device,
+    pub source: Source,
     pub name: Option<String>,
 }
-impl From<cpal::Device> for Device {
-    fn from(value: cpal::Device) -> Self {
-        let name = value.name().ok();
+impl From<Source> for Device {
+    fn from(value: Source) -> Self {
+        let name = value.name();
         Self {
-            cpal_device: value,
+            source: value,
             name: name,
         }
     }
 }
+impl From<cpal::Device> for Device {
+    fn from(value: cpal::Device) -> Self {
+        Source::Device(value).into()
+    }
+}
 
-#[derive(Debug, PartialEq)]
-pub enum Selected {
-    None,
-    Left,
-    Right,
-    Popup,
+use crate::config::Config;
+use crate::connection::{Connection, ConnectionEvent, StreamInfo};
+use crate::control::{ControlMessage, ControlRequest};
+use crate::file_signaling::{InboxOffer, watch_inbox, write_atomic};
+use crate::latency::LatencyBreakdown;
+use crate::metadata::{StreamMetadata, TrackInfo, poll_mpris};
+use crate::net::{FailedPairings, NetEvent, NetHandler, PairingSecret};
+use crate::reconnect::{ReconnectPolicy, SignalingChannel};
+use crate::sdp::{self, DescriptionFormat};
+use crate::session::{SessionEvent, SessionState};
+use crate::source::{Source, SourceStream};
+use crate::stats::ConnectionStats;
+
+/// What a frontend can ask the app to do, see [`App::execute`].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Command {
+    /// index into [`App::devices`], streamed from once the next offer is answered
+    SelectDevice {
+        device: usize,
+    },
+    /// creates a peer connection waiting for an offer
+    Connect,
+    /// an offer to answer, or the receiver's answer to an ICE restart
+    Description {
+        description: String,
+    },
+    /// answers the offer in the configured offer file
+    AnswerOfferFile,
+    /// like [`Command::Description`] with the clipboard contents
+    PasteFromClipboard,
+    CopyDescription,
+    /// puts a click into the stream to measure the latency
+    LatencyClick,
+    Stop,
+    Disconnect,
+    DismissError,
+    Quit,
 }
-impl Default for Selected {
-    fn default() -> Self {
-        Selected::None
-    }
+
+/// A user interface driving the [`App`], see [`App::run`].
+pub trait Frontend {
+    /// Shows the current state, called after every command and event.
+    fn render(&mut self, app: &App) -> anyhow::Result<()>;
+    /// Waits for user input, `None` when it only changed the frontend's own state.
+    /// Raced against the app's events, so it has to be cancel safe.
+    fn next_command(&mut self) -> impl Future<Output = Option<Command>>;
 }
 
-use crate::connection::Connection;
-use crate::ui::{draw_left_panel, draw_popup, draw_right_panel};
+/// Something for the app to handle that didn't come from the user.
+enum AppEvent {
+    /// once per second, stats, reconnects and status updates for the receiver
+    Tick,
+    Connection(ConnectionEvent),
+    Control(ControlRequest),
+    Net(NetEvent),
+    Inbox(InboxOffer),
+    /// the capture device failed while running
+    Source(cpal::StreamError),
+}
 
 pub struct App {
     pub exit: bool,
     devices: Vec<Device>,
     pub selected_device: usize,
     connection: Option<Connection>,
-    pub list_state: ListState,
-    state: Selected,
 
     pub local_desc: String,
+    /// `local_desc` in the compact format, the full one doesn't fit into a QR code
+    pub qr_desc: String,
+    /// created on first use, kept alive since on X11 the owner has to serve the contents
+    clipboard: Option<arboard::Clipboard>,
+    /// result of the last user action, shown in the status panel
+    notice: Option<String>,
+    /// failed user action, shown until dismissed
+    error: Option<String>,
+
+    session: SessionState,
+    ice_state: Option<RTCIceConnectionState>,
+
+    stream: Option<SourceStream>,
 
-    event_stream: EventStream,
-    connection_status: String,
+    pairing: Arc<PairingSecret>,
+    net_events: mpsc::UnboundedReceiver<NetEvent>,
+    inbox_offers: mpsc::UnboundedReceiver<InboxOffer>,
+    source_error_sender: mpsc::UnboundedSender<cpal::StreamError>,
+    source_errors: mpsc::UnboundedReceiver<cpal::StreamError>,
+    /// how the current session was negotiated
+    signaling: Option<SignalingChannel>,
+    reconnect: ReconnectPolicy,
+    /// offers on the signaling port that failed authentication or were malformed
+    failed_pairings: FailedPairings,
 
-    stream: Option<Stream>,
-    listener: tokio::net::TcpListener,
+    config: Config,
+    /// set once streaming starts
+    stream_info: Option<StreamInfo>,
+    stats: Option<ConnectionStats>,
+    /// peak per channel over the last tick
+    levels: Vec<f32>,
+    stats_timer: tokio::time::Interval,
+
+    control_sender: mpsc::UnboundedSender<ControlRequest>,
+    control_requests: mpsc::UnboundedReceiver<ControlRequest>,
+
+    metadata: StreamMetadata,
+    /// tells the `playerctl` poller whether anyone receives the track
+    mpris_active: watch::Sender<bool>,
+    /// latest track from the poller, stays `None` without `mpris` in the config
+    tracks: watch::Receiver<Option<TrackInfo>>,
+    /// what the receiver was told last, `None` until the control channel is open
+    metadata_sent: Option<StreamMetadata>,
 }
 
 impl App {
-    pub async fn new() -> anyhow::Result<Self> {
+    pub async fn new(config: Config) -> anyhow::Result<Self> {
+        let (control_sender, control_requests) = mpsc::unbounded_channel();
+        let (net_sender, net_events) = mpsc::unbounded_channel();
+        let pairing = Arc::new(PairingSecret::new(config.pairing_secret.clone()));
+        let listener = tokio::net::TcpListener::bind("0.0.0.0:2138").await?;
+        tokio::spawn(NetHandler::new(listener, pairing.clone(), net_sender).run());
+        let (inbox_sender, inbox_offers) = mpsc::unbounded_channel();
+        let (source_error_sender, source_errors) = mpsc::unbounded_channel();
+        let (mpris_active, active) = watch::channel(false);
+        let (track_sender, tracks) = watch::channel(None);
+        if config.mpris {
+            tokio::spawn(poll_mpris(
+                config.mpris_player.clone(),
+                active,
+                track_sender,
+            ));
+        }
+        if let Some(inbox) = config.signaling.inbox.clone() {
+            tokio::spawn(watch_inbox(inbox, config.signaling.clone(), inbox_sender));
+        }
         Ok(Self {
             exit: Default::default(),
             devices: Default::default(),
             selected_device: Default::default(),
             connection: Default::default(),
-            list_state: Default::default(),
-            state: Default::default(),
             local_desc: Default::default(),
-            event_stream: Default::default(),
-            connection_status: Default::default(),
+            qr_desc: Default::default(),
+            clipboard: Default::default(),
+            notice: Default::default(),
+            error: Default::default(),
+            session: Default::default(),
+            ice_state: Default::default(),
             stream: Default::default(),
-            listener: tokio::net::TcpListener::bind("0.0.0.0:2138").await?,
+            pairing,
+            net_events,
+            inbox_offers,
+            source_error_sender,
+            source_errors,
+            signaling: Default::default(),
+            reconnect: Default::default(),
+            failed_pairings: Default::default(),
+            config,
+            stream_info: Default::default(),
+            stats: Default::default(),
+            levels: Default::default(),
+            stats_timer: tokio::time::interval(tokio::time::Duration::from_secs(1)),
+            control_sender,
+            control_requests,
+            metadata: Default::default(),
+            mpris_active,
+            tracks,
+            metadata_sent: Default::default(),
         })
     }
     pub fn scan_devices(&mut self) -> anyhow::Result<()> {
@@ -92,187 +210,560 @@ impl App {
             }
             self.devices.push(device.clone().into());
         }
-        self.list_state.select(Some(self.selected_device));
+        for file in self.config.files.iter() {
+            self.devices.push(Source::File(file.clone()).into());
+        }
+        for signal in self.config.signals.iter() {
+            self.devices.push(Source::Signal(*signal).into());
+        }
         Ok(())
     }
-    pub async fn run(&mut self, terminal: &mut DefaultTerminal) -> anyhow::Result<()> {
+    pub async fn run(&mut self, frontend: &mut impl Frontend) -> anyhow::Result<()> {
+        let result = self.event_loop(frontend).await;
+        // also when the loop failed, so the receiver sees the close and recordings get finished
+        self.disconnect().await;
+        result
+    }
+    async fn event_loop(&mut self, frontend: &mut impl Frontend) -> anyhow::Result<()> {
         while !self.exit {
-            terminal.draw(|frame| self.draw(frame))?;
-            self.handle_crossterm_events().await?
+            frontend.render(self)?;
+            select! {
+                command = frontend.next_command() => {
+                    if let Some(command) = command
+                        && let Err(e) = self.execute(command).await
+                    {
+                        self.show_error(e);
+                    }
+                }
+                event = self.next_event() => self.handle_event(event).await,
+            }
+        }
+        Ok(())
+    }
+    /// Waits for the next event from the connection, the receiver, signaling or the timer.
+    /// Only receives, so it can be raced against the frontend.
+    async fn next_event(&mut self) -> AppEvent {
+        select! {
+            _ = self.stats_timer.tick() => AppEvent::Tick,
+            Some(event) = next_connection_event(&mut self.connection) => {
+                AppEvent::Connection(event)
+            }
+            Some(request) = self.control_requests.recv() => AppEvent::Control(request),
+            Some(event) = self.net_events.recv() => AppEvent::Net(event),
+            Some(offer) = self.inbox_offers.recv() => AppEvent::Inbox(offer),
+            Some(error) = self.source_errors.recv() => AppEvent::Source(error),
+        }
+    }
+    async fn handle_event(&mut self, event: AppEvent) {
+        match event {
+            AppEvent::Tick => {
+                self.check_reconnect();
+                self.levels = match &self.connection {
+                    Some(conn) => conn.controls().take_levels(),
+                    None => Vec::new(),
+                };
+                self.send_status().await;
+                self.publish_metadata().await;
+            }
+            AppEvent::Connection(event) => self.handle_connection_event(event).await,
+            AppEvent::Control(request) => self.handle_control_request(request).await,
+            AppEvent::Net(event) => self.handle_net_event(event).await,
+            AppEvent::Inbox(offer) => self.handle_inbox_offer(offer).await,
+            AppEvent::Source(error) => self.handle_source_error(error).await,
+        }
+    }
+    /// Stops streaming when the capture device is gone, other errors are only shown.
+    async fn handle_source_error(&mut self, error: cpal::StreamError) {
+        log::warn!("capture error: {error}");
+        if matches!(error, cpal::StreamError::DeviceNotAvailable) && self.is_streaming() {
+            self.stop_stream().await;
+            self.show_error(anyhow::anyhow!("capture stopped: {error}"));
+        } else {
+            self.notice = Some(format!("Capture: {error}"));
+        }
+    }
+    /// Stops capture and waits for the encoder to flush and finish the recording.
+    /// The connection stays up.
+    async fn stop_stream(&mut self) {
+        // dropping the source closes the channel the encode task reads from
+        self.stream = None;
+        if let Some(conn) = &self.connection {
+            conn.join_encoder().await;
+        }
+        self.stream_info = None;
+        self.session.handle(SessionEvent::StreamStopped);
+    }
+    /// Stops the stream and closes the peer connection.
+    async fn disconnect(&mut self) {
+        self.stop_stream().await;
+        self.signaling = None;
+        self.reconnect.reset();
+        self.stats = None;
+        self.ice_state = None;
+        self.metadata_sent = None;
+        if let Some(conn) = self.connection.take()
+            && let Err(e) = conn.close().await
+        {
+            self.notice = Some(format!("Closing the connection failed: {e}"));
         }
+        self.session.handle(SessionEvent::Disconnected);
+    }
+    /// Replaces any previous connection with a fresh one waiting for an offer.
+    async fn open_connection(&mut self) -> anyhow::Result<()> {
+        self.disconnect().await;
+        self.connection = Some(self.connect().await?);
+        self.session.handle(SessionEvent::Connect);
         Ok(())
     }
+    async fn handle_connection_event(&mut self, event: ConnectionEvent) {
+        match event {
+            ConnectionEvent::IceState(state) => self.ice_state = Some(state),
+            // changes without any action on our side
+            ConnectionEvent::PeerState(state) => {
+                self.session.handle(SessionEvent::PeerState(state))
+            }
+            ConnectionEvent::TrackError(e) => self.notice = Some(format!("Audio track: {e}")),
+            ConnectionEvent::StreamFailed(e) => {
+                self.stop_stream().await;
+                self.show_error(anyhow::anyhow!("streaming stopped: {e}"));
+            }
+            ConnectionEvent::SourceEnded => {
+                // a source the app stopped or replaced itself hasn't ended
+                if self.stream.as_ref().is_some_and(SourceStream::ended) {
+                    self.stop_stream().await;
+                    self.notice = Some("The source ended, streaming stopped".to_string());
+                }
+            }
+            ConnectionEvent::Stats(stats) => self.stats = Some(stats),
+            ConnectionEvent::RestartOffer(offer) => self.send_restart_offer(&offer),
+            ConnectionEvent::RestartFailed(e) => {
+                self.notice = Some(format!("ICE restart failed: {e}"))
+            }
+        }
+    }
+    pub fn ice_state(&self) -> Option<RTCIceConnectionState> {
+        self.ice_state
+    }
+    pub fn session(&self) -> &SessionState {
+        &self.session
+    }
     pub fn is_connected(&self) -> bool {
         if let Some(c) = &self.connection {
             return c.is_connected();
         }
         false
     }
+    pub fn is_streaming(&self) -> bool {
+        self.stream.is_some()
+    }
     pub fn connection_state(&self) -> Option<RTCPeerConnectionState> {
         if let Some(c) = &self.connection {
             return Some(c.connection_state());
         }
         None
     }
-    pub fn state(&self) -> &Selected {
-        &self.state
-    }
     pub fn devices(&self) -> &Vec<Device> {
         &self.devices
     }
-
-    fn draw(&mut self, frame: &mut Frame) {
-        let layout = Layout::default()
-            .direction(ratatui::layout::Direction::Horizontal)
-            .constraints(vec![Constraint::Percentage(50), Constraint::Percentage(50)])
-            .split(frame.area());
-        draw_left_panel(self, frame, &layout);
-        draw_right_panel(self, frame, &layout);
-        if self.state == Selected::Popup {
-            draw_popup(self, frame);
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+    pub fn stream_info(&self) -> Option<&StreamInfo> {
+        self.stream_info.as_ref()
+    }
+    pub fn stats(&self) -> Option<&ConnectionStats> {
+        self.stats.as_ref()
+    }
+    pub fn metadata(&self) -> &StreamMetadata {
+        &self.metadata
+    }
+    pub fn reconnect(&self) -> &ReconnectPolicy {
+        &self.reconnect
+    }
+    pub fn levels(&self) -> &[f32] {
+        &self.levels
+    }
+    pub fn error(&self) -> Option<&str> {
+        self.error.as_deref()
+    }
+    fn show_error(&mut self, error: anyhow::Error) {
+        self.error = Some(format!("{error:#}"));
+    }
+    pub fn notice(&self) -> Option<&str> {
+        self.notice.as_deref()
+    }
+    pub fn pairing(&self) -> &PairingSecret {
+        &self.pairing
+    }
+    pub fn failed_pairings(&self) -> &FailedPairings {
+        &self.failed_pairings
+    }
+    /// Refreshes the metadata and sends it to the receiver if it changed.
+    async fn publish_metadata(&mut self) {
+        let open = self
+            .connection
+            .as_ref()
+            .is_some_and(Connection::control_open);
+        // `playerctl` only runs while there is a receiver to tell
+        self.mpris_active
+            .send_if_modified(|active| std::mem::replace(active, open) != open);
+        self.metadata = StreamMetadata {
+            title: self.config.title.clone(),
+            device: self
+                .devices
+                .get(self.selected_device)
+                .and_then(|d| d.name.clone()),
+            track: self.tracks.borrow().clone(),
+        };
+        let Some(conn) = &self.connection else {
+            return;
+        };
+        if !open {
+            self.metadata_sent = None;
+            return;
+        }
+        if self.metadata_sent.as_ref() == Some(&self.metadata) {
+            return;
+        }
+        let message = ControlMessage::Metadata(self.metadata.clone());
+        if conn.send_control(&message).await.is_ok() {
+            self.metadata_sent = Some(self.metadata.clone());
+        }
+    }
+    pub fn latency(&self) -> Option<LatencyBreakdown> {
+        if !self.is_streaming() {
+            return None;
         }
+        let rtt = self.stats.as_ref().and_then(|s| s.rtt_ms);
+        Some(self.connection.as_ref()?.latency().breakdown(rtt))
     }
 
-    async fn handle_crossterm_events(&mut self) -> anyhow::Result<()> {
-        tokio::select! {
-               event = self.event_stream.next().fuse() =>{
-                if let Some(Ok(event)) = event{
-                    match event {
-                        // it's important to check that the event is a key press event as
-                        // crossterm also emits key release and repeat events on Windows.
-                        Event::Key(key_event) if key_event.kind == KeyEventKind::Press => {
-                            self.handle_key_event(key_event).await
-                        }
-                        Event::Paste(content) =>{
-                            self.handle_paste_event(content).await
-                        }
-                        _ => {}
-                    };
+    async fn handle_net_event(&mut self, event: NetEvent) {
+        match event {
+            NetEvent::Offer {
+                addr,
+                offer,
+                reply,
+                restarts,
+            } => {
+                self.notice = Some(format!("Offer from {addr}"));
+                let answer = self.answer_offer(&offer).await;
+                if answer.is_ok()
+                    && let Some(signaling) = &mut self.signaling
+                {
+                    signaling.restarts = Some(restarts);
                 }
-            },
-             _ = tokio::time::sleep(tokio::time::Duration::from_millis(100)) => {
-            // Sleep for a short duration to avoid busy waiting.
+                let _ = reply.send(answer);
+            }
+            NetEvent::RestartAnswer { addr, answer } => {
+                self.notice = Some(match self.apply_restart_answer(&answer).await {
+                    Ok(()) => format!("ICE restart answered by {addr}"),
+                    Err(e) => format!("ICE restart answer from {addr} failed: {e}"),
+                });
+            }
+            NetEvent::Rejected { addr, reason } => self.failed_pairings.push(addr, reason),
         }
-        // r = self.listener.accept()=>{
-
-        // }
+    }
+    /// Answers an offer dropped into the inbox with an answer file next to it.
+    async fn handle_inbox_offer(&mut self, InboxOffer { path, offer }: InboxOffer) {
+        let answer_path = self.config.signaling.answer_path(&path);
+        let result = match self.answer_offer(&offer).await {
+            Ok(answer) => write_atomic(&answer_path, answer.as_bytes()).map_err(Into::into),
+            Err(e) => Err(e),
+        };
+        self.notice = Some(match result {
+            Ok(()) => format!("Answered {}", path.display()),
+            Err(e) => format!("Answering {} failed: {e}", path.display()),
+        });
+    }
+    /// Answers an offer from any signaling channel and starts streaming to it.
+    async fn answer_offer(&mut self, offer: &str) -> anyhow::Result<String> {
+        if !self.session.can_accept_offer() {
+            anyhow::bail!("already streaming to a receiver, disconnect first");
+        }
+        let answer = self.answer_offer_inner(offer).await;
+        if let Err(e) = &answer {
+            self.session.handle(SessionEvent::Error(e.to_string()));
+        }
+        answer
+    }
+    async fn answer_offer_inner(&mut self, offer: &str) -> anyhow::Result<String> {
+        // a bad offer must not tear down the current connection
+        let (offer, format) = sdp::decode(offer)?;
+        if offer.sdp_type != RTCSdpType::Offer {
+            anyhow::bail!("expected an offer, got {}", offer.sdp_type);
+        }
+        if self.session != SessionState::AwaitingOffer {
+            self.open_connection().await?;
+        }
+        let conn = self.connection.as_ref().expect("connection created above");
+        self.signaling = Some(SignalingChannel {
+            format,
+            restarts: None,
+        });
+        self.reconnect.reset();
+        conn.set_remote_description(offer).await?;
+        conn.create_answer().await?;
+        let answer = conn
+            .get_local_desc()
+            .await
+            .ok_or_else(|| anyhow::anyhow!("no local description"))?;
+        let encoded = sdp::encode(&answer, format)?;
+        self.set_local_desc(encoded.clone(), &answer);
+        self.start_stream()?;
+        self.session.handle(SessionEvent::Answered);
+        Ok(encoded)
+    }
+    /// Restarts ICE once the policy says so, the offer comes back as a connection event.
+    /// Capture and the encode task keep running meanwhile.
+    fn check_reconnect(&mut self) {
+        let Some(conn) = &self.connection else {
+            return;
+        };
+        if self.is_streaming() && self.reconnect.update(conn.connection_state()) {
+            conn.restart_ice();
+        }
+    }
+    /// Sends an ICE restart offer back the way the session was negotiated.
+    fn send_restart_offer(&mut self, offer: &RTCSessionDescription) {
+        // the stream was stopped while the offer was gathered
+        if !self.is_streaming() {
+            return;
+        }
+        let format = self
+            .signaling
+            .as_ref()
+            .map_or(DescriptionFormat::Full, |s| s.format);
+        let encoded = match sdp::encode(offer, format) {
+            Ok(offer) => offer,
+            Err(e) => {
+                self.notice = Some(format!("ICE restart failed: {e}"));
+                return;
+            }
+        };
+        let restarts = self.signaling.as_ref().and_then(|s| s.restarts.as_ref());
+        if restarts.is_some_and(|r| r.send(encoded.clone()).is_ok()) {
+            self.notice = Some("ICE restart offer sent to the receiver".to_string());
+        } else {
+            // no signaling connection left, has to go through the user
+            self.set_local_desc(encoded, offer);
+            self.notice = Some(
+                "ICE restart offer ready, send it to the receiver and paste its answer".to_string(),
+            );
         }
+    }
+    async fn apply_restart_answer(&self, answer: &str) -> anyhow::Result<()> {
+        let Some(conn) = &self.connection else {
+            anyhow::bail!("no connection");
+        };
+        if !self.session.can_accept_answer() {
+            anyhow::bail!("no ICE restart in progress");
+        }
+        let (answer, _) = sdp::decode(answer)?;
+        if answer.sdp_type != RTCSdpType::Answer {
+            anyhow::bail!("expected an answer, got {}", answer.sdp_type);
+        }
+        conn.set_remote_description(answer).await?;
         Ok(())
     }
-    async fn handle_paste_event(&mut self, content: String) {
-        self.connection_status = "Paste".to_string();
-        if let Some(conn) = &self.connection {
-            let d = BASE64_URL_SAFE.decode(content.trim()).unwrap();
-
-            let offer = serde_json::from_slice::<RTCSessionDescription>(&d).unwrap();
-            conn.set_remote_description(offer).await.unwrap();
-            conn.create_answer().await.unwrap();
-            let a = conn.get_local_desc().await.unwrap();
-            let json_str = serde_json::to_string(&a).unwrap();
-
-            let mut b64 = String::new();
-            base64::prelude::BASE64_STANDARD.encode_string(&json_str, &mut b64);
-            self.local_desc = b64;
-        }
-    }
-    async fn handle_key_event(&mut self, key_event: KeyEvent) {
-        match key_event.code {
-            KeyCode::Char('q') => self.exit(),
-            KeyCode::Up | KeyCode::Char('w') => match self.state {
-                Selected::None => todo!(),
-                Selected::Left => {
-                    self.list_state.select_previous();
-                }
-                Selected::Right => {}
-                Selected::Popup => todo!(),
+    async fn connect(&self) -> anyhow::Result<Connection> {
+        Connection::new(
+            RTCConfiguration {
+                ice_servers: vec![RTCIceServer {
+                    urls: vec!["stun:stun.l.google.com:19302".to_owned()],
+                    ..Default::default()
+                }],
+                ..Default::default()
             },
-            KeyCode::Down | KeyCode::Char('s') => match self.state {
-                Selected::None => todo!(),
-                Selected::Left => {
-                    self.list_state.select_next();
+            self.control_sender.clone(),
+        )
+        .await
+    }
+    fn clipboard(&mut self) -> anyhow::Result<&mut arboard::Clipboard> {
+        if self.clipboard.is_none() {
+            self.clipboard = Some(arboard::Clipboard::new()?);
+        }
+        Ok(self.clipboard.as_mut().expect("clipboard created above"))
+    }
+    /// Shows `text` as the local description, the QR code gets the compact form of `desc`.
+    fn set_local_desc(&mut self, text: String, desc: &RTCSessionDescription) {
+        self.qr_desc = if text.starts_with(sdp::COMPACT_PREFIX) {
+            text.clone()
+        } else {
+            // falls back to the full text, the QR view reports it as too large then
+            sdp::encode(desc, DescriptionFormat::Compact).unwrap_or_else(|_| text.clone())
+        };
+        self.local_desc = text;
+    }
+    fn copy_local_desc(&mut self) {
+        if self.local_desc.is_empty() {
+            self.notice = Some("Nothing to copy yet".to_string());
+            return;
+        }
+        let desc = self.local_desc.clone();
+        self.notice = Some(match self.clipboard().and_then(|c| Ok(c.set_text(desc)?)) {
+            Ok(()) => "Description copied to the clipboard".to_string(),
+            Err(e) => format!("No clipboard available ({e}), copy the description from the panel"),
+        });
+    }
+    async fn paste_from_clipboard(&mut self) -> anyhow::Result<()> {
+        match self.clipboard().and_then(|c| Ok(c.get_text()?)) {
+            Ok(content) => {
+                self.notice = Some("Offer read from the clipboard".to_string());
+                return self.apply_description(content).await;
+            }
+            Err(e) => {
+                self.notice = Some(format!(
+                    "No clipboard available ({e}), paste the offer into the terminal instead"
+                ))
+            }
+        }
+        Ok(())
+    }
+    /// Answers an offer, or applies the answer to an ICE restart.
+    async fn apply_description(&mut self, content: String) -> anyhow::Result<()> {
+        let (desc, _) = sdp::decode(&content).context("pasted text is not a description")?;
+        if desc.sdp_type == RTCSdpType::Answer {
+            // the receiver's answer to an ICE restart
+            self.apply_restart_answer(&content).await?;
+            self.notice = Some("ICE restart answer applied".to_string());
+            return Ok(());
+        }
+        self.answer_offer(&content).await?;
+        Ok(())
+    }
+    /// Runs a command from a frontend, commands the session can't take right now are ignored.
+    pub async fn execute(&mut self, command: Command) -> anyhow::Result<()> {
+        match command {
+            Command::SelectDevice { device } => {
+                if device >= self.devices.len() {
+                    anyhow::bail!("no device {device}");
                 }
-                Selected::Right => {}
-                Selected::Popup => todo!(),
-            },
-            KeyCode::Right | KeyCode::Char('d') => match self.state {
-                _ => self.state = Selected::Right,
-            },
-            KeyCode::Left | KeyCode::Char('a') => match self.state {
-                _ => self.state = Selected::Left,
-            },
-            KeyCode::Enter => match self.state {
-                Selected::Left => self.selected_device = self.list_state.selected().unwrap(),
-                Selected::Right => {
-                    self.connection = Some(
-                        Connection::new(RTCConfiguration {
-                            ice_servers: vec![RTCIceServer {
-                                urls: vec!["stun:stun.l.google.com:19302".to_owned()],
-                                ..Default::default()
-                            }],
-                            ..Default::default()
-                        })
-                        .await
-                        .unwrap(),
-                    );
-                    self.connection_status = "Started".to_string();
+                self.selected_device = device;
+            }
+            Command::Connect if self.session.can_connect() => {
+                self.open_connection()
+                    .await
+                    .context("creating the peer connection failed")?;
+            }
+            Command::Description { description } => self.apply_description(description).await?,
+            Command::AnswerOfferFile if self.session.can_accept_offer() => {
+                let signaling = self.config.signaling.clone();
+                let content = std::fs::read_to_string(&signaling.offer_file)
+                    .with_context(|| format!("reading {}", signaling.offer_file.display()))?;
+                let answer = self.answer_offer(&content).await?;
+                write_atomic(&signaling.answer_file, answer.as_bytes())
+                    .with_context(|| format!("writing {}", signaling.answer_file.display()))?;
+            }
+            Command::PasteFromClipboard => self.paste_from_clipboard().await?,
+            Command::CopyDescription => self.copy_local_desc(),
+            Command::LatencyClick => {
+                if let Some(conn) = &self.connection
+                    && self.is_streaming()
+                {
+                    conn.latency().request_click();
+                    let _ = conn.send_control(&ControlMessage::Click).await;
                 }
-                _ => {}
-            },
-            KeyCode::Char('e') => match self.state {
-                Selected::Right => {
-                    self.connection_status = "Paste".to_string();
-                    if let Some(conn) = &self.connection {
-                        let mut content = String::new();
-                        OpenOptions::new()
-                            .read(true)
-                            .open("./desc.txt")
-                            .unwrap()
-                            .read_to_string(&mut content)
-                            .unwrap();
-                        let d = BASE64_URL_SAFE.decode(content.trim()).unwrap();
-
-                        let offer = serde_json::from_slice::<RTCSessionDescription>(&d).unwrap();
-                        conn.set_remote_description(offer).await.unwrap();
-                        conn.create_answer().await.unwrap();
-                        let a = conn.get_local_desc().await.unwrap();
-                        let json_str = serde_json::to_string(&a).unwrap();
-
-                        let mut b64 = String::new();
-                        base64::prelude::BASE64_STANDARD.encode_string(&json_str, &mut b64);
-                        self.local_desc = b64.clone();
-                        let mut f = OpenOptions::new()
-                            .create(true)
-                            .write(true)
-                            .open("./desc1.txt")
-                            .unwrap();
-                        f.write_all(b64.as_bytes()).unwrap();
+            }
+            Command::Stop if self.session.can_stop() => {
+                self.stop_stream().await;
+                self.notice = Some("Stream stopped".to_string());
+            }
+            Command::Disconnect if self.session.can_disconnect() => {
+                self.disconnect().await;
+                self.notice = Some("Disconnected".to_string());
+            }
+            Command::DismissError => self.error = None,
+            Command::Quit => self.exit(),
+            _ => {}
+        }
+        Ok(())
+    }
 
-                        let device = &self.devices[self.selected_device].cpal_device;
-                        let (stream, receiver, _sender) = crate::create_stream(&device).unwrap();
-                        self.stream = Some(stream);
-                        self.stream.as_ref().unwrap().play().unwrap();
-                        let config = device.default_output_config().unwrap();
-                        // let config =
-                        //     device
-                        //         .supported_output_configs()
-                        //         .unwrap()
-                        //         .into_iter()
-                        //         .find(|v| {
-                        //             v.try_with_sample_rate(SampleRate(48000)).is_some()
-                        //                 && v.channels() == 2
-                        //                 && v.sample_format() == SampleFormat::F32
-                        //         });
-                        // let config = config.unwrap().with_sample_rate(SampleRate(48000));
-                        conn.start(receiver, config).unwrap();
-                    }
+    /// Starts the selected device and feeds it into the connection, replacing the
+    /// previous source if there was one.
+    fn start_stream(&mut self) -> anyhow::Result<()> {
+        let Some(conn) = &self.connection else {
+            return Ok(());
+        };
+        // the old encode task flushes and ends once its source is gone
+        self.stream = None;
+        let device = self
+            .devices
+            .get(self.selected_device)
+            .context("no audio device selected")?;
+        let (stream, receiver, config) = device.source.start(&self.source_error_sender)?;
+        // kept only once the encoder runs, it would capture into a closed channel otherwise
+        self.stream_info = Some(conn.start(receiver, config, &self.config)?);
+        self.stream = Some(stream);
+        self.session.handle(SessionEvent::StreamStarted);
+        Ok(())
+    }
+    async fn handle_control_request(&mut self, request: ControlRequest) {
+        let Some(conn) = &self.connection else {
+            return;
+        };
+        let controls = conn.controls();
+        match request {
+            ControlRequest::Mute { muted } => controls.set_muted(muted),
+            ControlRequest::Volume { volume } => controls.set_volume(volume),
+            ControlRequest::Bitrate { bitrate } => controls.set_bitrate(bitrate),
+            ControlRequest::SwitchDevice { device } => {
+                let found = self
+                    .devices
+                    .iter()
+                    .position(|d| d.name.as_deref() == Some(device.as_str()));
+                let Some(i) = found else {
+                    let message = format!("no device named {device}");
+                    let _ = conn.send_control(&ControlMessage::Error { message }).await;
+                    return;
+                };
+                self.selected_device = i;
+                if self.is_streaming()
+                    && let Err(e) = self.start_stream()
+                    && let Some(conn) = &self.connection
+                {
+                    let message = format!("switching to {device} failed: {e}");
+                    let _ = conn.send_control(&ControlMessage::Error { message }).await;
                 }
-                _ => {}
-            },
-            _ => {}
+            }
+            ControlRequest::Status => {}
+            ControlRequest::ClickHeard => {
+                conn.latency().click_heard();
+                return;
+            }
         }
+        self.send_status().await;
+    }
+    /// Pushes the current device, settings and levels to the receiver.
+    async fn send_status(&self) {
+        let Some(conn) = &self.connection else {
+            return;
+        };
+        let controls = conn.controls();
+        let status = ControlMessage::Status {
+            device: self
+                .devices
+                .get(self.selected_device)
+                .and_then(|d| d.name.clone()),
+            sample_rate: self.stream_info.as_ref().map(|i| i.sample_rate),
+            muted: controls.muted(),
+            volume: controls.volume(),
+            bitrate: controls.bitrate(),
+            levels: self.levels.clone(),
+        };
+        let _ = conn.send_control(&status).await;
     }
 
     fn exit(&mut self) {
         self.exit = true;
     }
 }
+
+/// Pends forever without a connection, for use in `select!`.
+async fn next_connection_event(connection: &mut Option<Connection>) -> Option<ConnectionEvent> {
+    match connection {
+        Some(conn) => conn.next_event().await,
+        None => std::future::pending().await,
+    }
+}
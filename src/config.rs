@@ -0,0 +1,76 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+
+use crate::file_signaling::FileSignaling;
+use crate::keymap::Keymap;
+use crate::resampler::ResamplerKind;
+use crate::signal::TestSignal;
+use crate::source::FileSource;
+
+pub const CONFIG_PATH: &str = "./audio_share.json";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub resampler: ResamplerKind,
+    /// wav files listed as extra sources next to the capture devices
+    pub files: Vec<FileSource>,
+    /// every stream is also saved here as `<unix time>.opus`, `<unix time>-<n>.opus` when several
+    /// start within a second
+    pub recordings: Option<PathBuf>,
+    /// generated test signals listed as sources
+    pub signals: Vec<TestSignal>,
+    /// stream title shown to listeners
+    pub title: Option<String>,
+    /// publish the track of the local MPRIS player (through `playerctl`)
+    pub mpris: bool,
+    /// only follow this MPRIS player instead of the active one
+    pub mpris_player: Option<String>,
+    /// secret offers on the signaling port have to be signed with, a random PIN is shown when unset.
+    /// Use a long one on untrusted networks, see [`crate::net::PairingSecret`]
+    pub pairing_secret: Option<String>,
+    pub signaling: FileSignaling,
+    /// `error`, `warn`, `info`, `debug` or `trace`
+    pub log_level: String,
+    /// records are appended here, the terminal belongs to the TUI
+    pub log_file: Option<PathBuf>,
+    /// TUI key bindings, e.g. `{ "quit": ["q", "Esc"] }`
+    pub keys: Keymap,
+}
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            resampler: Default::default(),
+            files: Default::default(),
+            recordings: Default::default(),
+            signals: TestSignal::defaults(),
+            title: Default::default(),
+            mpris: Default::default(),
+            mpris_player: Default::default(),
+            pairing_secret: Default::default(),
+            signaling: Default::default(),
+            log_level: "info".to_string(),
+            log_file: Some("./audio_share.log".into()),
+            keys: Default::default(),
+        }
+    }
+}
+impl Config {
+    /// Loads the config from `path`, falling back to defaults when the file doesn't exist.
+    pub fn load(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let content = std::fs::read_to_string(path)?;
+        let config: Self = serde_json::from_str(&content)?;
+        for signal in &config.signals {
+            signal
+                .validate()
+                .with_context(|| format!("invalid signal in {}", path.display()))?;
+        }
+        Ok(config)
+    }
+}
@@ -1,19 +1,28 @@
 use std::{
-    sync::Arc,
-    time::{Duration, Instant},
+    fs::File,
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
-use tokio::sync::Notify;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
 use webrtc::{
     api::{
         APIBuilder,
         interceptor_registry::register_default_interceptors,
         media_engine::{MIME_TYPE_OPUS, MediaEngine},
     },
+    data_channel::{
+        RTCDataChannel, data_channel_init::RTCDataChannelInit,
+        data_channel_message::DataChannelMessage, data_channel_state::RTCDataChannelState,
+    },
     ice_transport::ice_connection_state::RTCIceConnectionState,
     interceptor::registry::Registry,
     peer_connection::{
-        RTCPeerConnection, configuration::RTCConfiguration,
+        RTCPeerConnection, configuration::RTCConfiguration, offer_answer_options::RTCOfferOptions,
         peer_connection_state::RTCPeerConnectionState,
         sdp::session_description::RTCSessionDescription,
     },
@@ -21,14 +30,33 @@ use webrtc::{
     track::track_local::track_local_static_sample::TrackLocalStaticSample,
 };
 
+use crate::config::Config;
+use crate::control::{
+    CONTROL_CHANNEL_ID, CONTROL_CHANNEL_LABEL, ControlMessage, ControlRequest, StreamControls,
+};
+use crate::encoder::{OpusFramer, to_stereo};
+use crate::latency::LatencyProbe;
+use crate::ogg::OggOpusWriter;
+use crate::resampler::ResamplerKind;
+use crate::stats::{ConnectionStats, ReceiverReportStats};
+
 pub struct Connection {
     peer_connection: Arc<RTCPeerConnection>,
     audio_track: Arc<TrackLocalStaticSample>,
-    connected_notify: Arc<Notify>,
+    event_sender: mpsc::UnboundedSender<ConnectionEvent>,
+    events: mpsc::UnboundedReceiver<ConnectionEvent>,
     rtc_sender: Arc<RTCRtpSender>,
+    control_channel: Arc<RTCDataChannel>,
+    controls: Arc<StreamControls>,
+    latency: Arc<LatencyProbe>,
+    /// ends on its own once the source passed to [`Connection::start`] stops
+    encode_task: Mutex<Option<JoinHandle<()>>>,
 }
 impl Connection {
-    pub async fn new(rtc_config: RTCConfiguration) -> anyhow::Result<Self> {
+    pub async fn new(
+        rtc_config: RTCConfiguration,
+        control_requests: mpsc::UnboundedSender<ControlRequest>,
+    ) -> anyhow::Result<Self> {
         let mut media_engine = MediaEngine::default();
         media_engine.register_default_codecs()?;
         let mut registry = Registry::new();
@@ -50,34 +78,130 @@ impl Connection {
             "test".to_owned(),
         ));
         let rtc_sender = peer_connection.add_track(audio_track.clone()).await?;
-        let connected_notify = Arc::new(Notify::new());
+        let receiver_report = Arc::new(Mutex::new(None));
         {
-            let connected_notify = connected_notify.clone();
+            // RTCP has to be read for the interceptors to process it, keep the receiver
+            // reports for the stats while at it
+            let rtc_sender = rtc_sender.clone();
+            let receiver_report = receiver_report.clone();
+            tokio::spawn(async move {
+                // receivers can report on other sources too
+                let ssrc = rtc_sender
+                    .get_parameters()
+                    .await
+                    .encodings
+                    .first()
+                    .map(|e| e.ssrc);
+                while let Ok((packets, _)) = rtc_sender.read_rtcp().await {
+                    for packet in packets.iter() {
+                        if let Some(ssrc) = ssrc
+                            && let Some(report) =
+                                ReceiverReportStats::from_packet(packet.as_ref(), ssrc)
+                        {
+                            *receiver_report.lock().unwrap() = Some(report);
+                        }
+                    }
+                }
+            });
+        }
+        let control_channel = peer_connection
+            .create_data_channel(
+                CONTROL_CHANNEL_LABEL,
+                Some(RTCDataChannelInit {
+                    negotiated: Some(CONTROL_CHANNEL_ID),
+                    ..Default::default()
+                }),
+            )
+            .await?;
+        {
+            let channel = Arc::downgrade(&control_channel);
+            control_channel.on_message(Box::new(move |msg: DataChannelMessage| {
+                let request = serde_json::from_slice::<ControlRequest>(&msg.data);
+                let control_requests = control_requests.clone();
+                let channel = channel.clone();
+                Box::pin(async move {
+                    match request {
+                        Ok(request) => {
+                            let _ = control_requests.send(request);
+                        }
+                        Err(e) => {
+                            let message = ControlMessage::Error {
+                                message: format!("invalid request: {e}"),
+                            };
+                            if let (Some(channel), Ok(json)) =
+                                (channel.upgrade(), serde_json::to_string(&message))
+                            {
+                                let _ = channel.send_text(json).await;
+                            }
+                        }
+                    }
+                })
+            }));
+        }
+        let (event_sender, events) = mpsc::unbounded_channel();
+        {
+            let events = event_sender.clone();
             peer_connection.on_ice_connection_state_change(Box::new(
                 move |connection_state: RTCIceConnectionState| {
-                    println!("ICE changed {}", &connection_state);
-                    if connection_state == RTCIceConnectionState::Connected {
-                        connected_notify.notify_waiters();
-                    }
+                    log::info!("ICE connection state: {connection_state}");
+                    let _ = events.send(ConnectionEvent::IceState(connection_state));
                     Box::pin(async {})
                 },
             ));
+            let events = event_sender.clone();
             peer_connection.on_peer_connection_state_change(Box::new(
                 move |s: RTCPeerConnectionState| {
-                    println!("Peer Connection State has changed: {s}");
-
+                    log::info!("peer connection state: {s}");
+                    let _ = events.send(ConnectionEvent::PeerState(s));
                     Box::pin(async {})
                 },
             ));
         }
+        {
+            // pushed like the state changes so the UI doesn't have to poll
+            let peer_connection = Arc::downgrade(&peer_connection);
+            let receiver_report = receiver_report.clone();
+            let events = event_sender.clone();
+            tokio::spawn(async move {
+                let mut interval = tokio::time::interval(STATS_INTERVAL);
+                let mut previous = None;
+                loop {
+                    interval.tick().await;
+                    let Some(peer_connection) = peer_connection.upgrade() else {
+                        break;
+                    };
+                    let receiver_report = *receiver_report.lock().unwrap();
+                    let stats = ConnectionStats::collect(
+                        &peer_connection,
+                        receiver_report,
+                        previous.as_ref(),
+                    )
+                    .await;
+                    previous = Some(stats.clone());
+                    if events.send(ConnectionEvent::Stats(stats)).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
         Ok(Self {
             peer_connection,
             audio_track,
-            connected_notify: Arc::new(Notify::new()),
+            event_sender,
+            events,
             rtc_sender,
+            control_channel,
+            controls: Default::default(),
+            latency: Default::default(),
+            encode_task: Default::default(),
         })
     }
 
+    /// Next state change, stats snapshot or encoder error. Events of a dropped connection are
+    /// gone with it, so they can't be mistaken for the ones of its replacement.
+    pub async fn next_event(&mut self) -> Option<ConnectionEvent> {
+        self.events.recv().await
+    }
     pub async fn set_remote_description(
         &self,
         desc: RTCSessionDescription,
@@ -94,13 +218,56 @@ impl Connection {
         let _ = gather_complete.recv().await;
         Ok(())
     }
+    /// Creates an offer with new ICE credentials in the background, it arrives as
+    /// [`ConnectionEvent::RestartOffer`] once gathering is done. The remote has to answer it
+    /// for the connection to come back. Tracks and the encode task stay untouched.
+    pub fn restart_ice(&self) {
+        let peer_connection = self.peer_connection.clone();
+        let events = self.event_sender.clone();
+        tokio::spawn(async move {
+            let event = match create_restart_offer(&peer_connection).await {
+                Ok(offer) => ConnectionEvent::RestartOffer(Box::new(offer)),
+                Err(e) => ConnectionEvent::RestartFailed(e.to_string()),
+            };
+            let _ = events.send(event);
+        });
+    }
     pub async fn get_local_desc(&self) -> Option<RTCSessionDescription> {
         self.peer_connection.local_description().await
     }
     pub fn get_sender(&self) -> Arc<RTCRtpSender> {
         self.rtc_sender.clone()
     }
+    pub fn controls(&self) -> &Arc<StreamControls> {
+        &self.controls
+    }
+    pub fn latency(&self) -> &Arc<LatencyProbe> {
+        &self.latency
+    }
+    pub fn control_open(&self) -> bool {
+        self.control_channel.ready_state() == RTCDataChannelState::Open
+    }
+    /// Sends a message over the control channel, dropped while the channel isn't open.
+    pub async fn send_control(&self, message: &ControlMessage) -> anyhow::Result<()> {
+        if !self.control_open() {
+            return Ok(());
+        }
+        self.control_channel
+            .send_text(serde_json::to_string(message)?)
+            .await?;
+        Ok(())
+    }
+    /// Waits for the encode task to flush the last frames and finish the recording.
+    /// The source has to be stopped first, otherwise this waits forever.
+    pub async fn join_encoder(&self) {
+        let task = self.encode_task.lock().unwrap().take();
+        if let Some(task) = task {
+            let _ = task.await;
+        }
+    }
+    /// Closes the peer connection so the remote sees the session end instead of a timeout.
     pub async fn close(self) -> Result<(), webrtc::Error> {
+        self.join_encoder().await;
         self.peer_connection.close().await
     }
     pub fn is_connected(&self) -> bool {
@@ -113,14 +280,34 @@ impl Connection {
         &self,
         receiver: tokio::sync::broadcast::Receiver<Vec<f32>>,
         config: cpal::SupportedStreamConfig,
-    ) -> anyhow::Result<()> {
-        dbg!(&config, config.sample_rate());
-        let mut encoder =
-            opus::Encoder::new(48000, opus::Channels::Stereo, opus::Application::Audio)?;
+        options: &Config,
+    ) -> anyhow::Result<StreamInfo> {
+        log::info!(
+            "starting stream: {} channels at {} Hz",
+            config.channels(),
+            config.sample_rate().0
+        );
+        let mut framer = OpusFramer::new()?;
+
+        let (recording, file) = match &options.recordings {
+            Some(dir) => {
+                let (path, file) = create_recording(dir)?;
+                (Some(path), Some(file))
+            }
+            None => (None, None),
+        };
+        let mut ogg = match file {
+            Some(file) => Some(OggOpusWriter::new(
+                BufWriter::new(file),
+                2,
+                config.sample_rate().0,
+                framer.lookahead()? as u16,
+            )?),
+            None => None,
+        };
 
         let track = self.audio_track.clone();
         let mut r = receiver;
-        dbg!(&r);
 
         // let spec = hound::WavSpec {
         //     channels: config.channels(),
@@ -144,80 +331,100 @@ impl Connection {
             Some(crate::resampler::Resampler::new(
                 config.sample_rate().0 as usize,
                 48_000,
-                config.channels() as usize,
+                2,
+                options.resampler,
             )?)
         };
+        let info = StreamInfo {
+            resampler: resampler.as_ref().map(|r| r.kind()),
+            recording,
+            sample_rate: config.sample_rate().0,
+        };
+        let controls = self.controls.clone();
+        let channels = config.channels() as usize;
+        // blocks of other layouts are mapped into this one
+        let mut stereo = Vec::new();
+        let mut bitrate = None;
+        let latency = self.latency.clone();
+        let events = self.event_sender.clone();
+        latency.set_stream(
+            config.sample_rate().0,
+            resampler.as_ref().map_or(0, |r| r.output_delay()),
+            framer.lookahead()?,
+        );
 
         // let samples_per_ms = 48000 / 1000;
         // let samples_per_segment = samples_per_ms * 10;
         // let total_values = samples_per_segment * 2;
 
-        let mut frame_size = None;
-
-        tokio::spawn(async move {
-            let mut left = Vec::new();
-            while let Ok(v) = r.recv().await {
+        let task = tokio::spawn(async move {
+            // set when the stream can't go on, the app stops the source on it
+            let mut failure = None;
+            // 48 kHz stereo samples handed to the encoder, the recording ends after them
+            let mut encoded = 0;
+            loop {
+                let mut v = match r.recv().await {
+                    Ok(v) => v,
+                    // the blocks are gone, carrying on beats stopping the stream
+                    Err(RecvError::Lagged(skipped)) => {
+                        log::warn!("encoder fell behind, skipped {skipped} blocks");
+                        continue;
+                    }
+                    // the source was stopped or ended, see `SourceEnded` below
+                    Err(RecvError::Closed) => break,
+                };
+                // everything after this only handles stereo
+                if channels != 2 {
+                    to_stereo(&v, channels, &mut stereo);
+                    std::mem::swap(&mut v, &mut stereo);
+                }
                 // for a in v.iter() {
                 //     use cpal::Sample;
                 //     let sample = f32::from_sample(*a);
                 //     writer_o.write_sample(sample).unwrap();
                 // }
-                let pcm = if let Some(resampler) = resampler.as_mut() {
-                    resampler.process(&v)
-                } else {
-                    v
-                };
-                left.extend_from_slice(&pcm);
-
-                if frame_size.is_none() {
-                    let l = left.len();
-                    let options = vec![960, 1920, 2880];
-                    let full_chunks = options.iter().map(|v| l / v);
-                    let m = full_chunks
-                        .filter(|v| *v > 0)
-                        .enumerate()
-                        .min_by(|(_, v), (_, v2)| v.cmp(v2))
-                        .unwrap_or((0, 0));
-                    // let reminders = options.iter().map(|v| l % v);
-                    let v = match m.0 {
-                        0 => 960,
-                        1 => 1920,
-                        2 => 2880,
-                        _ => 960,
-                    };
-                    frame_size = Some(v);
+                controls.apply(&mut v, 2);
+                latency.inject_click(&mut v, 2);
+                if controls.bitrate() != bitrate {
+                    bitrate = controls.bitrate();
+                    let _ = framer.set_bitrate(bitrate);
                 }
-
-                let frame_size = frame_size.unwrap();
-                let full_chunks = left.len() / frame_size;
-                let remainder = left.len() % frame_size;
-
-                let mut frames = Vec::new();
-
-                for chunk in 0..full_chunks {
-                    let buffered_pcm = &left[chunk * frame_size..(chunk + 1) * frame_size];
-                    let frame = encoder
-                        .encode_vec_float(&buffered_pcm, buffered_pcm.len() * 2)
-                        .unwrap();
-                    frames.push(frame);
-                }
-                if remainder == 0 {
-                    left.clear();
+                let buffered = framer.pcm_mut().len();
+                if let Some(resampler) = resampler.as_mut() {
+                    if let Err(e) = resampler.process_into(&v, framer.pcm_mut()) {
+                        failure = Some(format!("resampling: {e}"));
+                        break;
+                    }
                 } else {
-                    left.copy_within(full_chunks * frame_size.., 0);
-                    left.truncate(remainder);
-                    // println!("Leftover data {} mode: {}", left.len(), frame_size);
+                    framer.pcm_mut().extend_from_slice(&v);
                 }
-                let l = frame_size / 2 / 48; // 48kHz
+                encoded += framer.pcm_mut().len() - buffered;
+
+                let frames = match framer.encode() {
+                    Ok(frames) => frames,
+                    Err(e) => {
+                        failure = Some(format!("encoding: {e}"));
+                        break;
+                    }
+                };
+                latency.record(
+                    r.len() * v.len() / 2,
+                    resampler.as_ref().map_or(0, |r| r.buffered_frames()),
+                    framer.pcm_mut().len() / 2,
+                    framer.frame_size().unwrap_or_default() / 2,
+                );
+                record(&mut ogg, &framer, &frames);
                 for frame in frames.into_iter() {
-                    track
+                    let written = track
                         .write_sample(&webrtc::media::Sample {
                             data: frame.into(),
-                            duration: Duration::from_millis(l as u64),
+                            duration: framer.frame_duration(),
                             ..Default::default()
                         })
-                        .await
-                        .unwrap();
+                        .await;
+                    if let Err(e) = written {
+                        report_track_error(&events, format!("sending: {e}"));
+                    }
                 }
                 // for a in resampled_pcm.iter() {
                 //     use cpal::Sample;
@@ -225,7 +432,155 @@ impl Connection {
                 //     writer_r.write_sample(sample).unwrap();
                 // }
             }
+            // capture stopped, push out what the resampler is still holding
+            if let Some(resampler) = resampler.as_mut() {
+                let buffered = framer.pcm_mut().len();
+                if let Err(e) = resampler.flush(framer.pcm_mut()) {
+                    report_track_error(&events, format!("resampling: {e}"));
+                }
+                encoded += framer.pcm_mut().len() - buffered;
+            }
+            let frames = framer.finish().unwrap_or_else(|e| {
+                report_track_error(&events, format!("encoding: {e}"));
+                Vec::new()
+            });
+            record(&mut ogg, &framer, &frames);
+            if let Some(ogg) = ogg
+                && let Err(e) = ogg.finish(Some(encoded as u64 / 2))
+            {
+                log::warn!("finishing the recording failed: {e}");
+            }
+            for frame in frames {
+                let _ = track
+                    .write_sample(&webrtc::media::Sample {
+                        data: frame.into(),
+                        duration: framer.frame_duration(),
+                        ..Default::default()
+                    })
+                    .await;
+            }
+            match failure {
+                Some(error) => {
+                    log::warn!("audio track stopped: {error}");
+                    let _ = events.send(ConnectionEvent::StreamFailed(error));
+                }
+                None => {
+                    let _ = events.send(ConnectionEvent::SourceEnded);
+                }
+            }
         });
-        Ok(())
+        // a replaced task finishes by itself once its source is gone
+        *self.encode_task.lock().unwrap() = Some(task);
+        Ok(info)
+    }
+}
+
+async fn create_restart_offer(
+    peer_connection: &RTCPeerConnection,
+) -> anyhow::Result<RTCSessionDescription> {
+    let offer = peer_connection
+        .create_offer(Some(RTCOfferOptions {
+            ice_restart: true,
+            ..Default::default()
+        }))
+        .await?;
+
+    let mut gather_complete = peer_connection.gathering_complete_promise().await;
+
+    peer_connection.set_local_description(offer).await?;
+
+    let _ = gather_complete.recv().await;
+    peer_connection
+        .local_description()
+        .await
+        .ok_or_else(|| anyhow::anyhow!("no local description"))
+}
+
+fn report_track_error(events: &mpsc::UnboundedSender<ConnectionEvent>, error: String) {
+    log::warn!("audio track: {error}");
+    let _ = events.send(ConnectionEvent::TrackError(error));
+}
+
+/// How often [`ConnectionEvent::Stats`] is sent.
+const STATS_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Sent from the peer connection callbacks and the encode task, see [`Connection::next_event`].
+#[derive(Debug, Clone)]
+pub enum ConnectionEvent {
+    IceState(RTCIceConnectionState),
+    PeerState(RTCPeerConnectionState),
+    /// the encode task failed to process or send audio
+    TrackError(String),
+    /// the encode task gave up, the source feeding it should be stopped
+    StreamFailed(String),
+    /// the source feeding the encode task closed its channel, also when it was stopped on
+    /// purpose, see [`crate::source::SourceStream::ended`]
+    SourceEnded,
+    /// see [`Connection::restart_ice`]
+    RestartOffer(Box<RTCSessionDescription>),
+    RestartFailed(String),
+    Stats(ConnectionStats),
+}
+
+/// What [`Connection::start`] set up for the stream.
+pub struct StreamInfo {
+    /// `None` when the source already runs at 48 kHz
+    pub resampler: Option<ResamplerKind>,
+    pub recording: Option<PathBuf>,
+    /// of the source, before resampling
+    pub sample_rate: u32,
+}
+
+/// Creates `<unix time>.opus` in `dir`, streams started within the same second get a
+/// `-<n>` suffix instead of overwriting each other.
+fn create_recording(dir: &Path) -> anyhow::Result<(PathBuf, File)> {
+    std::fs::create_dir_all(dir)?;
+    let secs = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    let mut n = 0;
+    loop {
+        let name = match n {
+            0 => format!("{secs}.opus"),
+            n => format!("{secs}-{n}.opus"),
+        };
+        let path = dir.join(name);
+        match File::create_new(&path) {
+            Ok(file) => return Ok((path, file)),
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => n += 1,
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
+/// Appends the packets to the recording, a failing recording is dropped without
+/// interrupting the stream.
+fn record<W: Write>(ogg: &mut Option<OggOpusWriter<W>>, framer: &OpusFramer, frames: &[Vec<u8>]) {
+    let Some(writer) = ogg else {
+        return;
+    };
+    let samples = framer.frame_size().unwrap_or_default() as u64 / 2;
+    for frame in frames {
+        if let Err(e) = writer.write_packet(frame, samples) {
+            log::warn!("recording stopped: {e}");
+            *ogg = None;
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recordings_in_the_same_second_get_their_own_file() {
+        let dir = std::env::temp_dir().join(format!("audio_share_rec_{}", std::process::id()));
+        let paths = (0..3)
+            .map(|_| create_recording(&dir).unwrap().0)
+            .collect::<Vec<_>>();
+        assert_ne!(paths[0], paths[1]);
+        assert_ne!(paths[1], paths[2]);
+        assert_ne!(paths[0], paths[2]);
+        assert!(paths.iter().all(|p| p.exists()));
+        std::fs::remove_dir_all(&dir).unwrap();
     }
 }
@@ -0,0 +1,223 @@
+//! JSON messages exchanged over the `control` data channel.
+//!
+//! The channel is negotiated out of band with id [`CONTROL_CHANNEL_ID`], so the receiver has to
+//! create it with `{ negotiated: true, id: 0 }` before making its offer.
+
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, AtomicI32, AtomicU32, Ordering};
+
+use serde::{Deserialize, Serialize};
+
+use crate::metadata::StreamMetadata;
+
+pub const CONTROL_CHANNEL_LABEL: &str = "control";
+pub const CONTROL_CHANNEL_ID: u16 = 0;
+
+/// Receiver -> sender.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ControlRequest {
+    Mute {
+        muted: bool,
+    },
+    /// linear gain, 1.0 leaves the signal untouched
+    Volume {
+        volume: f32,
+    },
+    /// bits per second, `None` lets opus decide
+    Bitrate {
+        bitrate: Option<i32>,
+    },
+    /// name as listed in the sender's device list
+    SwitchDevice {
+        device: String,
+    },
+    Status,
+    /// answer to [`ControlMessage::Click`] once the click came out of the decoder
+    ClickHeard,
+}
+
+/// Sender -> receiver.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ControlMessage {
+    Status {
+        device: Option<String>,
+        sample_rate: Option<u32>,
+        muted: bool,
+        volume: f32,
+        bitrate: Option<i32>,
+        /// peak per channel since the last status, 0.0 - 1.0
+        levels: Vec<f32>,
+    },
+    Error {
+        message: String,
+    },
+    /// sent whenever the title, device or playing track changes
+    Metadata(StreamMetadata),
+    /// a click was put into the audio, reply with [`ControlRequest::ClickHeard`] when it arrives
+    Click,
+}
+
+/// Stream settings the encode task picks up on its next block.
+#[derive(Debug)]
+pub struct StreamControls {
+    muted: AtomicBool,
+    /// `f32` bits
+    volume: AtomicU32,
+    /// 0 means auto
+    bitrate: AtomicI32,
+    peaks: Mutex<Vec<f32>>,
+}
+impl Default for StreamControls {
+    fn default() -> Self {
+        Self {
+            muted: AtomicBool::new(false),
+            volume: AtomicU32::new(1f32.to_bits()),
+            bitrate: AtomicI32::new(0),
+            peaks: Mutex::new(Vec::new()),
+        }
+    }
+}
+impl StreamControls {
+    pub fn muted(&self) -> bool {
+        self.muted.load(Ordering::Relaxed)
+    }
+    pub fn set_muted(&self, muted: bool) {
+        self.muted.store(muted, Ordering::Relaxed);
+    }
+    pub fn volume(&self) -> f32 {
+        f32::from_bits(self.volume.load(Ordering::Relaxed))
+    }
+    pub fn set_volume(&self, volume: f32) {
+        self.volume
+            .store(volume.clamp(0., 4.).to_bits(), Ordering::Relaxed);
+    }
+    pub fn bitrate(&self) -> Option<i32> {
+        Some(self.bitrate.load(Ordering::Relaxed)).filter(|b| *b > 0)
+    }
+    pub fn set_bitrate(&self, bitrate: Option<i32>) {
+        self.bitrate.store(bitrate.unwrap_or(0), Ordering::Relaxed);
+    }
+    /// Applies mute and volume to interleaved `pcm` and tracks the peak levels.
+    pub fn apply(&self, pcm: &mut [f32], channels: usize) {
+        let gain = if self.muted() { 0. } else { self.volume() };
+        let mut peaks = self.peaks.lock().unwrap();
+        peaks.resize(channels, 0.);
+        for frame in pcm.chunks_exact_mut(channels) {
+            for (sample, peak) in frame.iter_mut().zip(peaks.iter_mut()) {
+                *sample *= gain;
+                *peak = peak.max(sample.abs());
+            }
+        }
+    }
+    /// Peaks since the previous call.
+    pub fn take_levels(&self) -> Vec<f32> {
+        let mut peaks = self.peaks.lock().unwrap();
+        let levels = peaks.clone();
+        peaks.iter_mut().for_each(|p| *p = 0.);
+        levels
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip<T>(value: T, json: &str)
+    where
+        T: Serialize + serde::de::DeserializeOwned + PartialEq + std::fmt::Debug,
+    {
+        assert_eq!(serde_json::to_string(&value).unwrap(), json);
+        assert_eq!(serde_json::from_str::<T>(json).unwrap(), value);
+    }
+
+    #[test]
+    fn requests_match_the_wire_format() {
+        round_trip(
+            ControlRequest::Mute { muted: true },
+            r#"{"type":"mute","muted":true}"#,
+        );
+        round_trip(
+            ControlRequest::Volume { volume: 0.5 },
+            r#"{"type":"volume","volume":0.5}"#,
+        );
+        round_trip(
+            ControlRequest::Bitrate {
+                bitrate: Some(64000),
+            },
+            r#"{"type":"bitrate","bitrate":64000}"#,
+        );
+        round_trip(
+            ControlRequest::Bitrate { bitrate: None },
+            r#"{"type":"bitrate","bitrate":null}"#,
+        );
+        round_trip(
+            ControlRequest::SwitchDevice {
+                device: "Speakers".into(),
+            },
+            r#"{"type":"switch_device","device":"Speakers"}"#,
+        );
+        round_trip(ControlRequest::Status, r#"{"type":"status"}"#);
+        round_trip(ControlRequest::ClickHeard, r#"{"type":"click_heard"}"#);
+    }
+
+    #[test]
+    fn messages_match_the_wire_format() {
+        round_trip(
+            ControlMessage::Status {
+                device: Some("Speakers".into()),
+                sample_rate: Some(48000),
+                muted: false,
+                volume: 1.,
+                bitrate: None,
+                levels: vec![0.25, 0.5],
+            },
+            r#"{"type":"status","device":"Speakers","sample_rate":48000,"muted":false,"volume":1.0,"bitrate":null,"levels":[0.25,0.5]}"#,
+        );
+        round_trip(
+            ControlMessage::Error {
+                message: "no such device".into(),
+            },
+            r#"{"type":"error","message":"no such device"}"#,
+        );
+        round_trip(ControlMessage::Click, r#"{"type":"click"}"#);
+    }
+
+    #[test]
+    fn unknown_requests_are_rejected() {
+        assert!(serde_json::from_str::<ControlRequest>(r#"{"type":"reboot"}"#).is_err());
+        assert!(serde_json::from_str::<ControlRequest>(r#"{"type":"mute"}"#).is_err());
+    }
+
+    #[test]
+    fn apply_scales_and_tracks_peaks() {
+        let controls = StreamControls::default();
+        let mut pcm = [0.5, -0.25, -0.1, 0.2];
+        controls.apply(&mut pcm, 2);
+        assert_eq!(pcm, [0.5, -0.25, -0.1, 0.2]);
+
+        controls.set_volume(0.5);
+        controls.apply(&mut pcm, 2);
+        assert_eq!(pcm, [0.25, -0.125, -0.05, 0.1]);
+        // peaks cover both blocks
+        assert_eq!(controls.take_levels(), [0.5, 0.25]);
+        assert_eq!(controls.take_levels(), [0., 0.]);
+
+        controls.set_muted(true);
+        controls.apply(&mut pcm, 2);
+        assert_eq!(pcm, [0.; 4]);
+        assert_eq!(controls.take_levels(), [0., 0.]);
+    }
+
+    #[test]
+    fn volume_is_clamped() {
+        let controls = StreamControls::default();
+        controls.set_volume(10.);
+        assert_eq!(controls.volume(), 4.);
+        controls.set_volume(-1.);
+        assert_eq!(controls.volume(), 0.);
+        controls.set_bitrate(Some(0));
+        assert_eq!(controls.bitrate(), None);
+    }
+}
@@ -0,0 +1,182 @@
+use std::time::Duration;
+
+/// Cuts interleaved 48 kHz stereo pcm into opus frames.
+/// Shared by [`crate::connection::Connection::start`] and the offline pipeline so both produce
+/// the same packets.
+pub struct OpusFramer {
+    encoder: opus::Encoder,
+    left: Vec<f32>,
+    frame_size: Option<usize>,
+}
+impl OpusFramer {
+    pub fn new() -> anyhow::Result<Self> {
+        Ok(Self {
+            encoder: opus::Encoder::new(48000, opus::Channels::Stereo, opus::Application::Audio)?,
+            left: Vec::new(),
+            frame_size: None,
+        })
+    }
+    /// Samples waiting for a full frame, new pcm gets appended here.
+    pub fn pcm_mut(&mut self) -> &mut Vec<f32> {
+        &mut self.left
+    }
+    /// Interleaved samples per frame, picked from the size of the first input.
+    pub fn frame_size(&self) -> Option<usize> {
+        self.frame_size
+    }
+    pub fn frame_duration(&self) -> Duration {
+        let l = self.frame_size.unwrap_or(960) / 2 / 48; // 48kHz
+        Duration::from_millis(l as u64)
+    }
+    /// `None` lets opus pick the bitrate.
+    pub fn set_bitrate(&mut self, bitrate: Option<i32>) -> anyhow::Result<()> {
+        self.encoder.set_bitrate(match bitrate {
+            Some(bits) => opus::Bitrate::Bits(bits),
+            None => opus::Bitrate::Auto,
+        })?;
+        Ok(())
+    }
+    /// Samples per channel the decoder has to drop from the start of the stream.
+    pub fn lookahead(&mut self) -> anyhow::Result<usize> {
+        Ok(self.encoder.get_lookahead()? as usize)
+    }
+    /// Encodes every full frame currently buffered.
+    pub fn encode(&mut self) -> anyhow::Result<Vec<Vec<u8>>> {
+        if self.left.is_empty() {
+            return Ok(Vec::new());
+        }
+        let frame_size = *self.frame_size.get_or_insert_with(|| {
+            let l = self.left.len();
+            let options = [960, 1920, 2880];
+            let full_chunks = options.iter().map(|v| l / v);
+            let m = full_chunks
+                .filter(|v| *v > 0)
+                .enumerate()
+                .min_by(|(_, v), (_, v2)| v.cmp(v2))
+                .unwrap_or((0, 0));
+            // let reminders = options.iter().map(|v| l % v);
+            match m.0 {
+                0 => 960,
+                1 => 1920,
+                2 => 2880,
+                _ => 960,
+            }
+        });
+
+        let full_chunks = self.left.len() / frame_size;
+        let remainder = self.left.len() % frame_size;
+
+        let mut frames = Vec::new();
+
+        for chunk in 0..full_chunks {
+            let buffered_pcm = &self.left[chunk * frame_size..(chunk + 1) * frame_size];
+            let frame = self
+                .encoder
+                .encode_vec_float(buffered_pcm, buffered_pcm.len() * 2)?;
+            frames.push(frame);
+        }
+        if remainder == 0 {
+            self.left.clear();
+        } else {
+            self.left.copy_within(full_chunks * frame_size.., 0);
+            self.left.truncate(remainder);
+            // println!("Leftover data {} mode: {}", left.len(), frame_size);
+        }
+        Ok(frames)
+    }
+    /// Pads the leftover samples with silence up to a full frame and encodes them.
+    pub fn finish(&mut self) -> anyhow::Result<Vec<Vec<u8>>> {
+        let mut frames = self.encode()?;
+        if let Some(frame_size) = self.frame_size
+            && !self.left.is_empty()
+        {
+            self.left.resize(frame_size, 0.);
+            frames.extend(self.encode()?);
+        }
+        Ok(frames)
+    }
+}
+
+/// Maps interleaved pcm with `channels` channels to the stereo [`OpusFramer`] takes.
+/// Mono goes to both sides. 5.1 and 7.1 in the WAV and ALSA order (front left, front right,
+/// center, LFE, then the surround pairs) are downmixed with the center and surrounds at -3 dB
+/// and without the LFE, scaled so a full scale input can't clip. Other layouts keep their
+/// first two channels.
+pub fn to_stereo(input: &[f32], channels: usize, output: &mut Vec<f32>) {
+    output.clear();
+    match channels {
+        1 => output.extend(input.iter().flat_map(|s| [*s, *s])),
+        6 | 8 => {
+            let side = std::f32::consts::FRAC_1_SQRT_2;
+            let pairs = (channels - 4) / 2;
+            let gain = 1. / (1. + side * (1 + pairs) as f32);
+            for frame in input.chunks_exact(channels) {
+                let center = frame[2] * side;
+                let (mut left, mut right) = (frame[0] + center, frame[1] + center);
+                for pair in frame[4..].chunks_exact(2) {
+                    left += pair[0] * side;
+                    right += pair[1] * side;
+                }
+                output.extend([left * gain, right * gain]);
+            }
+        }
+        _ => output.extend(input.chunks_exact(channels).flat_map(|f| [f[0], f[1]])),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::FRAC_1_SQRT_2;
+
+    use super::*;
+
+    #[test]
+    fn mono_goes_to_both_sides() {
+        let mut stereo = Vec::new();
+        to_stereo(&[0.25, -0.5], 1, &mut stereo);
+        assert_eq!(stereo, [0.25, 0.25, -0.5, -0.5]);
+    }
+
+    #[test]
+    fn surround_is_downmixed_without_the_lfe() {
+        let gain = 1. / (1. + 2. * FRAC_1_SQRT_2);
+        #[rustfmt::skip]
+        let input = [
+            // front left, front right, center, LFE, back left, back right
+            1., 0., 0., 1., 0., 0.,
+            0., 0., 1., 0., 0., 0.,
+            0., 0., 0., 0., 0., 1.,
+            1., 1., 1., 1., 1., 1.,
+        ];
+        let mut stereo = Vec::new();
+        to_stereo(&input, 6, &mut stereo);
+        let expected = [
+            gain,
+            0.,
+            FRAC_1_SQRT_2 * gain,
+            FRAC_1_SQRT_2 * gain,
+            0.,
+            FRAC_1_SQRT_2 * gain,
+            1.,
+            1.,
+        ];
+        assert_eq!(stereo.len(), expected.len());
+        for (sample, expected) in stereo.iter().zip(expected) {
+            assert!((sample - expected).abs() < 1e-6, "{stereo:?}");
+        }
+    }
+
+    #[test]
+    fn mapped_device_blocks_fill_whole_frames() {
+        // 10 ms from a mono and a 5.1 device, like `Connection::start` gets them
+        for channels in [1, 6] {
+            let block = vec![0.1; 480 * channels];
+            let mut framer = OpusFramer::new().unwrap();
+            to_stereo(&block, channels, framer.pcm_mut());
+            let frames = framer.encode().unwrap();
+            assert_eq!(frames.len(), 1, "{channels} channels");
+            assert_eq!(framer.frame_size(), Some(960));
+            assert!(framer.pcm_mut().is_empty());
+        }
+    }
+}
@@ -0,0 +1,230 @@
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+
+/// How often the inbox is scanned.
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+/// Offers younger than this may still be being written by scp or a sync tool.
+const SETTLE_TIME: Duration = Duration::from_millis(500);
+
+pub const OFFER_EXTENSION: &str = "offer";
+pub const ANSWER_EXTENSION: &str = "answer";
+
+/// Signaling through files, for shared folders, scp or sync tools.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FileSignaling {
+    /// read by the `answer_offer_file` action
+    pub offer_file: PathBuf,
+    /// written by the `answer_offer_file` action
+    pub answer_file: PathBuf,
+    /// watched for `*.offer` files, each one is answered with a `*.answer` file
+    pub inbox: Option<PathBuf>,
+    /// where answers to inbox offers go, next to the offer when unset
+    pub outbox: Option<PathBuf>,
+}
+impl Default for FileSignaling {
+    fn default() -> Self {
+        Self {
+            offer_file: "./desc.txt".into(),
+            answer_file: "./desc1.txt".into(),
+            inbox: None,
+            outbox: None,
+        }
+    }
+}
+impl FileSignaling {
+    /// Where the answer to the inbox offer at `offer` is written.
+    pub fn answer_path(&self, offer: &Path) -> PathBuf {
+        let answer = offer.with_extension(ANSWER_EXTENSION);
+        match (&self.outbox, answer.file_name()) {
+            (Some(outbox), Some(name)) => outbox.join(name),
+            _ => answer,
+        }
+    }
+}
+
+pub struct InboxOffer {
+    pub path: PathBuf,
+    pub offer: String,
+}
+
+/// Polls `inbox` and sends every new, unanswered offer once.
+pub async fn watch_inbox(
+    inbox: PathBuf,
+    signaling: FileSignaling,
+    offers: mpsc::UnboundedSender<InboxOffer>,
+) {
+    let mut seen = HashMap::new();
+    let mut interval = tokio::time::interval(POLL_INTERVAL);
+    while !offers.is_closed() {
+        interval.tick().await;
+        for offer in scan_inbox(&inbox, &signaling, &mut seen).await {
+            let _ = offers.send(offer);
+        }
+    }
+}
+
+/// The settled, unanswered offers in `inbox` that are not in `seen`, which maps the offers
+/// already returned to their modification time. Offers whose file is gone are dropped from it.
+async fn scan_inbox(
+    inbox: &Path,
+    signaling: &FileSignaling,
+    seen: &mut HashMap<PathBuf, SystemTime>,
+) -> Vec<InboxOffer> {
+    let mut offers = Vec::new();
+    let Ok(mut entries) = tokio::fs::read_dir(inbox).await else {
+        return offers;
+    };
+    let mut present = HashSet::new();
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        let path = entry.path();
+        if path.extension().and_then(|e| e.to_str()) != Some(OFFER_EXTENSION) {
+            continue;
+        }
+        present.insert(path.clone());
+        let Ok(modified) = entry.metadata().await.and_then(|m| m.modified()) else {
+            continue;
+        };
+        let settled = SystemTime::now()
+            .duration_since(modified)
+            .is_ok_and(|age| age >= SETTLE_TIME);
+        // a rewritten offer is answered again
+        let answered = tokio::fs::metadata(signaling.answer_path(&path))
+            .await
+            .and_then(|m| m.modified())
+            .is_ok_and(|answered| answered >= modified);
+        if !settled || answered || seen.get(&path) == Some(&modified) {
+            continue;
+        }
+        if let Ok(offer) = tokio::fs::read_to_string(&path).await {
+            seen.insert(path.clone(), modified);
+            offers.push(InboxOffer { path, offer });
+        }
+    }
+    seen.retain(|path, _| present.contains(path));
+    offers
+}
+
+/// Writes to a temporary file next to `path` and renames it, so readers never see a partial file.
+pub fn write_atomic(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    std::fs::write(&tmp, contents)?;
+    std::fs::rename(&tmp, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("audio_share_{name}_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn write_aged(path: &Path, contents: &str, age: Duration) {
+        std::fs::write(path, contents).unwrap();
+        std::fs::File::options()
+            .write(true)
+            .open(path)
+            .unwrap()
+            .set_modified(SystemTime::now() - age)
+            .unwrap();
+    }
+
+    fn names(offers: &[InboxOffer]) -> Vec<&str> {
+        let mut names: Vec<_> = offers
+            .iter()
+            .map(|o| o.path.file_name().unwrap().to_str().unwrap())
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn answer_goes_next_to_offer_or_into_outbox() {
+        let mut signaling = FileSignaling::default();
+        let offer = Path::new("/inbox/phone.offer");
+        assert_eq!(
+            signaling.answer_path(offer),
+            Path::new("/inbox/phone.answer")
+        );
+        signaling.outbox = Some("/outbox".into());
+        assert_eq!(
+            signaling.answer_path(offer),
+            Path::new("/outbox/phone.answer")
+        );
+    }
+
+    #[test]
+    fn write_atomic_replaces_and_cleans_up() {
+        let dir = temp_dir("write_atomic");
+        let path = dir.join("desc.txt");
+        write_atomic(&path, b"first").unwrap();
+        write_atomic(&path, b"second").unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "second");
+        assert!(!dir.join("desc.txt.tmp").exists());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn inbox_sends_settled_offers_once() {
+        let dir = temp_dir("inbox_once");
+        let signaling = FileSignaling::default();
+        let mut seen = HashMap::new();
+        let old = Duration::from_secs(10);
+        write_aged(&dir.join("a.offer"), "a", old);
+        write_aged(&dir.join("notes.txt"), "not an offer", old);
+        std::fs::write(dir.join("fresh.offer"), "still being written").unwrap();
+
+        let offers = scan_inbox(&dir, &signaling, &mut seen).await;
+        assert_eq!(names(&offers), ["a.offer"]);
+        assert_eq!(offers[0].offer, "a");
+        assert!(scan_inbox(&dir, &signaling, &mut seen).await.is_empty());
+
+        // a rewritten offer is sent again
+        write_aged(&dir.join("a.offer"), "a2", Duration::from_secs(5));
+        let offers = scan_inbox(&dir, &signaling, &mut seen).await;
+        assert_eq!(names(&offers), ["a.offer"]);
+        assert_eq!(offers[0].offer, "a2");
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn inbox_skips_answered_offers() {
+        let dir = temp_dir("inbox_answered");
+        let signaling = FileSignaling::default();
+        let mut seen = HashMap::new();
+        write_aged(&dir.join("a.offer"), "a", Duration::from_secs(10));
+        write_aged(&dir.join("b.offer"), "b", Duration::from_secs(10));
+        write_aged(&dir.join("a.answer"), "answer", Duration::from_secs(5));
+        // answered before the offer was rewritten
+        write_aged(&dir.join("b.answer"), "answer", Duration::from_secs(20));
+
+        let offers = scan_inbox(&dir, &signaling, &mut seen).await;
+        assert_eq!(names(&offers), ["b.offer"]);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn inbox_forgets_removed_offers() {
+        let dir = temp_dir("inbox_removed");
+        let signaling = FileSignaling::default();
+        let mut seen = HashMap::new();
+        write_aged(&dir.join("a.offer"), "a", Duration::from_secs(10));
+        write_aged(&dir.join("b.offer"), "b", Duration::from_secs(10));
+        assert_eq!(scan_inbox(&dir, &signaling, &mut seen).await.len(), 2);
+
+        std::fs::remove_file(dir.join("a.offer")).unwrap();
+        assert!(scan_inbox(&dir, &signaling, &mut seen).await.is_empty());
+        assert_eq!(seen.len(), 1);
+        assert!(seen.contains_key(&dir.join("b.offer")));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,264 @@
+//! Desktop frontend, drives the same [`App`] as the TUI.
+
+use std::cell::RefCell;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::task::{Context, Poll};
+
+use anyhow::Context as _;
+
+use slint::{CloseRequestResponse, ComponentHandle, ModelRc, StandardListViewItem, VecModel};
+use tokio::select;
+use tokio::sync::mpsc;
+
+use crate::app::{App, Command, Frontend};
+
+slint::slint! {
+    import { Button, GroupBox, HorizontalBox, StandardListView, TextEdit, VerticalBox } from "std-widgets.slint";
+
+    component LevelMeter inherits Rectangle {
+        in property <float> level;
+        height: 12px;
+        background: #303030;
+        border-radius: 2px;
+        Rectangle {
+            x: 0;
+            width: parent.width * min(root.level, 1.0);
+            background: root.level > 0.9 ? #d04040 : #40b040;
+            border-radius: 2px;
+        }
+    }
+
+    export component MainWindow inherits Window {
+        title: "audio_share";
+        preferred-width: 900px;
+        preferred-height: 600px;
+
+        in property <[StandardListViewItem]> devices;
+        in property <string> status;
+        in property <string> peer;
+        in property <string> pairing;
+        in property <string> description;
+        in property <string> notice;
+        in property <string> error;
+        // peak per channel, 0.0 - 1.0
+        in property <[float]> levels;
+        in property <bool> can-connect;
+        in property <bool> can-stop;
+        in property <bool> can-disconnect;
+
+        callback select-device(int);
+        callback connect();
+        callback answer-offer(string);
+        callback paste-offer();
+        callback copy-description();
+        callback stop();
+        callback disconnect();
+        callback dismiss-error();
+
+        // the app owns the selection, this only moves the highlight and doesn't call
+        // `select-device`, a choice the app didn't take snaps back on the next render
+        public function show-device(index: int) {
+            device-list.current-item = index;
+        }
+
+        HorizontalBox {
+            GroupBox {
+                title: "Available devices";
+                width: 40%;
+                device-list := StandardListView {
+                    model: root.devices;
+                    // only called for clicks and keys, not for `show-device`
+                    current-item-changed(i) => { root.select-device(i); }
+                }
+            }
+            VerticalBox {
+                GroupBox {
+                    title: "Status";
+                    VerticalBox {
+                        Text { text: root.status; font-weight: 700; }
+                        Text { text: "Peer connection: " + root.peer; }
+                        Text { text: "Pairing PIN: " + root.pairing; }
+                        for level in root.levels : LevelMeter { level: level; }
+                        Text { text: root.notice; wrap: word-wrap; }
+                    }
+                }
+                GroupBox {
+                    title: "Description";
+                    VerticalBox {
+                        TextEdit { text: root.description; read-only: true; wrap: word-wrap; }
+                        Text { text: "Receiver's offer:"; }
+                        offer := TextEdit { wrap: word-wrap; }
+                        HorizontalBox {
+                            Button { text: "Answer"; clicked => { root.answer-offer(offer.text); } }
+                            Button { text: "Paste offer"; clicked => { root.paste-offer(); } }
+                            Button { text: "Copy answer"; clicked => { root.copy-description(); } }
+                        }
+                    }
+                }
+                HorizontalBox {
+                    Button { text: "Connect"; enabled: root.can-connect; clicked => { root.connect(); } }
+                    Button { text: "Stop"; enabled: root.can-stop; clicked => { root.stop(); } }
+                    Button { text: "Disconnect"; enabled: root.can-disconnect; clicked => { root.disconnect(); } }
+                }
+            }
+        }
+
+        if root.error != "" : Rectangle {
+            x: 0;
+            y: 0;
+            width: parent.width;
+            height: parent.height;
+            background: #000000a0;
+            // keeps clicks away from the window behind
+            TouchArea {}
+            Rectangle {
+                width: 60%;
+                height: 30%;
+                background: #202020;
+                border-radius: 6px;
+                VerticalBox {
+                    Text { text: "Error"; font-weight: 700; color: #e05050; }
+                    Text { text: root.error; wrap: word-wrap; vertical-stretch: 1; }
+                    Button { text: "Dismiss"; clicked => { root.dismiss-error(); } }
+                }
+            }
+        }
+    }
+}
+
+/// How often the window is refreshed, the latency changes without an event.
+const REFRESH_INTERVAL: tokio::time::Duration = tokio::time::Duration::from_millis(100);
+
+struct Gui {
+    window: slint::Weak<MainWindow>,
+    commands: mpsc::UnboundedReceiver<Command>,
+    refresh: tokio::time::Interval,
+}
+impl Frontend for Gui {
+    fn render(&mut self, app: &App) -> anyhow::Result<()> {
+        if let Some(window) = self.window.upgrade() {
+            show(app, &window);
+        }
+        Ok(())
+    }
+    async fn next_command(&mut self) -> Option<Command> {
+        select! {
+            command = self.commands.recv() => Some(command.unwrap_or(Command::Quit)),
+            _ = self.refresh.tick() => None,
+        }
+    }
+}
+
+/// Polls `future` with `runtime` entered. The slint event loop knows nothing about tokio, the
+/// app's timers and `tokio::spawn` calls need the runtime as the current one.
+struct InRuntime<F> {
+    runtime: tokio::runtime::Handle,
+    future: Pin<Box<F>>,
+}
+impl<F: Future> Future for InRuntime<F> {
+    type Output = F::Output;
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<F::Output> {
+        let _guard = self.runtime.enter();
+        self.future.as_mut().poll(cx)
+    }
+}
+
+/// Runs the window until it is closed. Has to be called on the main thread within the tokio
+/// runtime, the app is driven from the slint event loop so it can stay on this thread.
+pub fn run(mut app: App) -> anyhow::Result<()> {
+    let runtime = tokio::runtime::Handle::try_current()
+        .context("the GUI has to be started within the tokio runtime")?;
+    let window = MainWindow::new()?;
+    window.set_devices(ModelRc::new(VecModel::from(
+        app.devices()
+            .iter()
+            .map(|d| StandardListViewItem::from(d.name.as_deref().unwrap_or("Error")))
+            .collect::<Vec<_>>(),
+    )));
+
+    let (commands, command_receiver) = mpsc::unbounded_channel();
+    let send = |command: Command| {
+        let commands = commands.clone();
+        move || {
+            let _ = commands.send(command.clone());
+        }
+    };
+    window.on_connect(send(Command::Connect));
+    window.on_paste_offer(send(Command::PasteFromClipboard));
+    window.on_copy_description(send(Command::CopyDescription));
+    window.on_stop(send(Command::Stop));
+    window.on_disconnect(send(Command::Disconnect));
+    window.on_dismiss_error(send(Command::DismissError));
+    window.on_select_device({
+        let commands = commands.clone();
+        move |device| {
+            let _ = commands.send(Command::SelectDevice {
+                device: device as usize,
+            });
+        }
+    });
+    window.on_answer_offer({
+        let commands = commands.clone();
+        move |description| {
+            let _ = commands.send(Command::Description {
+                description: description.to_string(),
+            });
+        }
+    });
+    // the window stays until the connection is closed, the app ends the event loop
+    window.window().on_close_requested({
+        let commands = commands.clone();
+        move || {
+            let _ = commands.send(Command::Quit);
+            CloseRequestResponse::KeepWindowShown
+        }
+    });
+
+    let mut gui = Gui {
+        window: window.as_weak(),
+        commands: command_receiver,
+        refresh: tokio::time::interval(REFRESH_INTERVAL),
+    };
+    let result = Rc::new(RefCell::new(Ok(())));
+    slint::spawn_local({
+        let result = result.clone();
+        InRuntime {
+            runtime,
+            future: Box::pin(async move {
+                *result.borrow_mut() = app.run(&mut gui).await;
+                let _ = slint::quit_event_loop();
+            }),
+        }
+    })?;
+    window.run()?;
+    result.replace(Ok(()))
+}
+
+fn show(app: &App, window: &MainWindow) {
+    let session = app.session();
+    window.invoke_show_device(app.selected_device as i32);
+    window.set_status(session.to_string().into());
+    window.set_peer(
+        match (app.connection_state(), app.ice_state()) {
+            (Some(state), Some(ice)) => format!("{state} (ICE {ice})"),
+            (Some(state), None) => state.to_string(),
+            (None, _) => "-".to_string(),
+        }
+        .into(),
+    );
+    window.set_pairing(
+        app.pairing()
+            .pin()
+            .as_deref()
+            .unwrap_or("shared secret from config")
+            .into(),
+    );
+    window.set_description(app.local_desc.as_str().into());
+    window.set_notice(app.notice().unwrap_or_default().into());
+    window.set_error(app.error().unwrap_or_default().into());
+    window.set_levels(ModelRc::new(VecModel::from(app.levels().to_vec())));
+    window.set_can_connect(session.can_connect());
+    window.set_can_stop(session.can_stop());
+    window.set_can_disconnect(session.can_disconnect());
+}
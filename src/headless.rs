@@ -0,0 +1,79 @@
+//! Frontend for scripts and services: [`Command`]s as JSON lines on stdin, the status as a JSON
+//! line on stdout whenever it changes.
+
+use serde::Serialize;
+use tokio::io::{AsyncBufReadExt, BufReader, Lines, Stdin};
+use tokio::select;
+
+use crate::app::{App, Command, Frontend};
+use crate::session::SessionState;
+
+#[derive(Serialize)]
+struct Status<'a> {
+    session: &'a SessionState,
+    device: Option<&'a str>,
+    /// the local description to hand to the receiver
+    description: &'a str,
+    notice: Option<&'a str>,
+    error: Option<&'a str>,
+}
+
+pub struct Headless {
+    /// `None` once stdin is closed, offers can still come in over the network then
+    commands: Option<Lines<BufReader<Stdin>>>,
+    last_status: String,
+}
+impl Default for Headless {
+    fn default() -> Self {
+        Self {
+            commands: Some(BufReader::new(tokio::io::stdin()).lines()),
+            last_status: String::new(),
+        }
+    }
+}
+impl Frontend for Headless {
+    fn render(&mut self, app: &App) -> anyhow::Result<()> {
+        let status = serde_json::to_string(&Status {
+            session: app.session(),
+            device: app
+                .devices()
+                .get(app.selected_device)
+                .and_then(|d| d.name.as_deref()),
+            description: &app.local_desc,
+            notice: app.notice(),
+            error: app.error(),
+        })?;
+        if status != self.last_status {
+            println!("{status}");
+            self.last_status = status;
+        }
+        Ok(())
+    }
+    async fn next_command(&mut self) -> Option<Command> {
+        select! {
+            command = read_command(&mut self.commands) => command,
+            _ = tokio::signal::ctrl_c() => Some(Command::Quit),
+        }
+    }
+}
+
+/// Pends forever once stdin is closed.
+async fn read_command(commands: &mut Option<Lines<BufReader<Stdin>>>) -> Option<Command> {
+    let Some(lines) = commands else {
+        return std::future::pending().await;
+    };
+    match lines.next_line().await {
+        Ok(Some(line)) if line.trim().is_empty() => None,
+        Ok(Some(line)) => match serde_json::from_str(&line) {
+            Ok(command) => Some(command),
+            Err(e) => {
+                eprintln!("invalid command: {e}");
+                None
+            }
+        },
+        Ok(None) | Err(_) => {
+            *commands = None;
+            None
+        }
+    }
+}
@@ -0,0 +1,368 @@
+//! Key bindings of the TUI, configurable under `keys` in the config.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+
+use crossterm::event::KeyCode;
+use serde::{Deserialize, Serialize};
+
+/// Part of the TUI an action belongs to, its keys only work while that part has focus.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Context {
+    Global,
+    Devices,
+    Session,
+    /// the error popup
+    Popup,
+}
+impl Context {
+    pub fn title(self) -> &'static str {
+        match self {
+            Context::Global => "Anywhere",
+            Context::Devices => "Available devices",
+            Context::Session => "Session",
+            Context::Popup => "Popup",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    Quit,
+    Help,
+    FocusDevices,
+    FocusSession,
+    ToggleLog,
+    ScrollLogUp,
+    ScrollLogDown,
+    MoveUp,
+    MoveDown,
+    SelectDevice,
+    Connect,
+    AnswerOfferFile,
+    PasteOffer,
+    CopyDescription,
+    ToggleQr,
+    LatencyClick,
+    Stop,
+    Disconnect,
+    Dismiss,
+}
+impl Action {
+    /// In the order the help lists them.
+    pub const ALL: [Action; 19] = [
+        Action::Quit,
+        Action::Help,
+        Action::FocusDevices,
+        Action::FocusSession,
+        Action::ToggleLog,
+        Action::ScrollLogUp,
+        Action::ScrollLogDown,
+        Action::MoveUp,
+        Action::MoveDown,
+        Action::SelectDevice,
+        Action::Connect,
+        Action::AnswerOfferFile,
+        Action::PasteOffer,
+        Action::CopyDescription,
+        Action::ToggleQr,
+        Action::LatencyClick,
+        Action::Stop,
+        Action::Disconnect,
+        Action::Dismiss,
+    ];
+
+    pub fn context(self) -> Context {
+        match self {
+            Action::Quit
+            | Action::Help
+            | Action::FocusDevices
+            | Action::FocusSession
+            | Action::ToggleLog
+            | Action::ScrollLogUp
+            | Action::ScrollLogDown => Context::Global,
+            Action::MoveUp | Action::MoveDown | Action::SelectDevice => Context::Devices,
+            Action::Connect
+            | Action::AnswerOfferFile
+            | Action::PasteOffer
+            | Action::CopyDescription
+            | Action::ToggleQr
+            | Action::LatencyClick
+            | Action::Stop
+            | Action::Disconnect => Context::Session,
+            Action::Dismiss => Context::Popup,
+        }
+    }
+    pub fn description(self) -> &'static str {
+        match self {
+            Action::Quit => "Quit",
+            Action::Help => "Help",
+            Action::FocusDevices => "Focus devices",
+            Action::FocusSession => "Focus session",
+            Action::ToggleLog => "Toggle log",
+            Action::ScrollLogUp => "Scroll log up",
+            Action::ScrollLogDown => "Scroll log down",
+            Action::MoveUp => "Move up",
+            Action::MoveDown => "Move down",
+            Action::SelectDevice => "Select",
+            Action::Connect => "Connect",
+            Action::AnswerOfferFile => "Answer offer file",
+            Action::PasteOffer => "Paste offer",
+            Action::CopyDescription => "Copy description",
+            Action::ToggleQr => "Toggle QR code",
+            Action::LatencyClick => "Latency click",
+            Action::Stop => "Stop",
+            Action::Disconnect => "Disconnect",
+            Action::Dismiss => "Dismiss",
+        }
+    }
+    fn default_keys(self) -> Vec<Key> {
+        let keys: &[KeyCode] = match self {
+            Action::Quit => &[KeyCode::Char('q')],
+            Action::Help => &[KeyCode::Char('?')],
+            Action::FocusDevices => &[KeyCode::Left, KeyCode::Char('a')],
+            Action::FocusSession => &[KeyCode::Right, KeyCode::Char('d')],
+            Action::ToggleLog => &[KeyCode::Char('L')],
+            Action::ScrollLogUp => &[KeyCode::PageUp],
+            Action::ScrollLogDown => &[KeyCode::PageDown],
+            Action::MoveUp => &[KeyCode::Up, KeyCode::Char('w')],
+            Action::MoveDown => &[KeyCode::Down, KeyCode::Char('s')],
+            Action::SelectDevice => &[KeyCode::Enter],
+            Action::Connect => &[KeyCode::Enter],
+            Action::AnswerOfferFile => &[KeyCode::Char('e')],
+            Action::PasteOffer => &[KeyCode::Char('p')],
+            Action::CopyDescription => &[KeyCode::Char('y')],
+            Action::ToggleQr => &[KeyCode::Char('v')],
+            Action::LatencyClick => &[KeyCode::Char('l')],
+            Action::Stop => &[KeyCode::Char('x')],
+            Action::Disconnect => &[KeyCode::Char('c')],
+            Action::Dismiss => &[KeyCode::Esc, KeyCode::Enter],
+        };
+        keys.iter().copied().map(Key).collect()
+    }
+}
+
+/// A single character, `Space`, `F1` - `F12` or a key name like `Enter` or `PageUp`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Key(pub KeyCode);
+impl FromStr for Key {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut chars = s.chars();
+        if let (Some(c), None) = (chars.next(), chars.next()) {
+            return Ok(Key(KeyCode::Char(c)));
+        }
+        let code = match s {
+            "Space" => KeyCode::Char(' '),
+            "Enter" => KeyCode::Enter,
+            "Esc" => KeyCode::Esc,
+            "Tab" => KeyCode::Tab,
+            "Backspace" => KeyCode::Backspace,
+            "Delete" => KeyCode::Delete,
+            "Insert" => KeyCode::Insert,
+            "Up" => KeyCode::Up,
+            "Down" => KeyCode::Down,
+            "Left" => KeyCode::Left,
+            "Right" => KeyCode::Right,
+            "Home" => KeyCode::Home,
+            "End" => KeyCode::End,
+            "PageUp" => KeyCode::PageUp,
+            "PageDown" => KeyCode::PageDown,
+            _ => match s.strip_prefix('F').and_then(|n| n.parse().ok()) {
+                Some(n @ 1..=12) => KeyCode::F(n),
+                _ => anyhow::bail!("unknown key {s}"),
+            },
+        };
+        Ok(Key(code))
+    }
+}
+impl TryFrom<String> for Key {
+    type Error = anyhow::Error;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+impl From<Key> for String {
+    fn from(value: Key) -> Self {
+        value.to_string()
+    }
+}
+impl fmt::Display for Key {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            KeyCode::Char(' ') => f.write_str("Space"),
+            KeyCode::Char(c) => write!(f, "{c}"),
+            KeyCode::F(n) => write!(f, "F{n}"),
+            // the names `from_str` takes
+            code => write!(f, "{code:?}"),
+        }
+    }
+}
+
+/// Keys per action, actions left out of the config keep their default keys.
+/// A key can only trigger one action per context.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(
+    try_from = "BTreeMap<Action, Vec<Key>>",
+    into = "BTreeMap<Action, Vec<Key>>"
+)]
+pub struct Keymap(BTreeMap<Action, Vec<Key>>);
+impl Default for Keymap {
+    fn default() -> Self {
+        Self(
+            Action::ALL
+                .into_iter()
+                .map(|action| (action, action.default_keys()))
+                .collect(),
+        )
+    }
+}
+impl TryFrom<BTreeMap<Action, Vec<Key>>> for Keymap {
+    type Error = anyhow::Error;
+
+    fn try_from(value: BTreeMap<Action, Vec<Key>>) -> Result<Self, Self::Error> {
+        let mut keymap = Self::default();
+        keymap.0.extend(value);
+        for (i, first) in Action::ALL.into_iter().enumerate() {
+            for second in Action::ALL.into_iter().skip(i + 1) {
+                if first.context() != second.context() {
+                    continue;
+                }
+                if let Some(key) = keymap
+                    .keys(first)
+                    .iter()
+                    .find(|key| keymap.keys(second).contains(key))
+                {
+                    anyhow::bail!(
+                        "{key} is bound to both {} and {} in {}",
+                        first.description(),
+                        second.description(),
+                        first.context().title()
+                    );
+                }
+            }
+        }
+        Ok(keymap)
+    }
+}
+impl From<Keymap> for BTreeMap<Action, Vec<Key>> {
+    fn from(value: Keymap) -> Self {
+        value.0
+    }
+}
+impl Keymap {
+    /// The action `code` triggers, `contexts` in order of precedence.
+    pub fn action(&self, code: KeyCode, contexts: &[Context]) -> Option<Action> {
+        contexts.iter().find_map(|context| {
+            Action::ALL.into_iter().find(|action| {
+                action.context() == *context && self.keys(*action).contains(&Key(code))
+            })
+        })
+    }
+    /// Empty when the action was unbound in the config.
+    pub fn keys(&self, action: Action) -> &[Key] {
+        self.0.get(&action).map_or(&[], Vec::as_slice)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keys_round_trip() {
+        for name in [
+            "a",
+            "Q",
+            "?",
+            "<",
+            "Space",
+            "Enter",
+            "Esc",
+            "Tab",
+            "Backspace",
+            "Delete",
+            "Insert",
+            "Up",
+            "Down",
+            "Left",
+            "Right",
+            "Home",
+            "End",
+            "PageUp",
+            "PageDown",
+            "F1",
+            "F12",
+        ] {
+            let key = name.parse::<Key>().unwrap();
+            assert_eq!(key.to_string(), name);
+        }
+        assert_eq!("Space".parse::<Key>().unwrap(), Key(KeyCode::Char(' ')));
+        assert_eq!("F5".parse::<Key>().unwrap(), Key(KeyCode::F(5)));
+        for invalid in ["", "F0", "F13", "enter", "Ctrl"] {
+            assert!(invalid.parse::<Key>().is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn defaults_have_no_conflicts() {
+        let keymap = Keymap::try_from(BTreeMap::new()).unwrap();
+        for action in Action::ALL {
+            assert_eq!(keymap.keys(action), action.default_keys(), "{action:?}");
+        }
+    }
+
+    #[test]
+    fn overrides_merge_over_the_defaults() {
+        let keymap: Keymap =
+            serde_json::from_str(r#"{"quit": ["Esc", "F10"], "toggle_qr": []}"#).unwrap();
+        assert_eq!(
+            keymap.keys(Action::Quit),
+            [Key(KeyCode::Esc), Key(KeyCode::F(10))]
+        );
+        assert!(keymap.keys(Action::ToggleQr).is_empty());
+        assert_eq!(keymap.keys(Action::Stop), Action::Stop.default_keys());
+
+        let global = [Context::Global];
+        assert_eq!(keymap.action(KeyCode::Esc, &global), Some(Action::Quit));
+        assert_eq!(keymap.action(KeyCode::Char('q'), &global), None);
+        let session = [Context::Session, Context::Global];
+        assert_eq!(keymap.action(KeyCode::Char('v'), &session), None);
+    }
+
+    #[test]
+    fn focused_context_takes_precedence() {
+        // the same key in different contexts is fine, the focused one wins
+        let keymap: Keymap = serde_json::from_str(r#"{"stop": ["q"]}"#).unwrap();
+        let session = [Context::Session, Context::Global];
+        assert_eq!(
+            keymap.action(KeyCode::Char('q'), &session),
+            Some(Action::Stop)
+        );
+        let devices = [Context::Devices, Context::Global];
+        assert_eq!(
+            keymap.action(KeyCode::Char('q'), &devices),
+            Some(Action::Quit)
+        );
+    }
+
+    #[test]
+    fn rejects_conflicts_within_a_context() {
+        // `c` is disconnect by default, also in the session panel
+        let error = serde_json::from_str::<Keymap>(r#"{"stop": ["c"]}"#).unwrap_err();
+        assert!(error.to_string().contains("bound to both"), "{error}");
+    }
+
+    #[test]
+    fn serializes_back_to_the_same_keymap() {
+        let keymap: Keymap = serde_json::from_str(r#"{"help": ["F1"]}"#).unwrap();
+        let json = serde_json::to_string(&keymap).unwrap();
+        let again: Keymap = serde_json::from_str(&json).unwrap();
+        assert_eq!(again.0, keymap.0);
+    }
+}
@@ -0,0 +1,155 @@
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+/// Buffer fill levels reported by the encode task, plus the state of the click test.
+#[derive(Debug, Default)]
+pub struct LatencyProbe {
+    /// frames waiting in the broadcast channel between capture and encoder
+    capture_frames: AtomicUsize,
+    /// input frames the resampler holds until its next chunk
+    resampler_frames: AtomicUsize,
+    /// resampler filter delay in 48 kHz frames
+    resampler_delay: AtomicUsize,
+    /// 48 kHz frames waiting for a full opus frame
+    framer_frames: AtomicUsize,
+    /// opus frame length in 48 kHz frames
+    opus_frame: AtomicUsize,
+    /// encoder lookahead in 48 kHz frames
+    opus_lookahead: AtomicUsize,
+    source_rate: AtomicUsize,
+
+    click_requested: AtomicBool,
+    click_sent: Mutex<Option<Instant>>,
+    click_round_trip: Mutex<Option<Duration>>,
+}
+impl LatencyProbe {
+    pub fn set_stream(&self, source_rate: u32, resampler_delay: usize, opus_lookahead: usize) {
+        self.source_rate
+            .store(source_rate as usize, Ordering::Relaxed);
+        self.resampler_delay
+            .store(resampler_delay, Ordering::Relaxed);
+        self.opus_lookahead.store(opus_lookahead, Ordering::Relaxed);
+    }
+    pub fn record(
+        &self,
+        capture_frames: usize,
+        resampler_frames: usize,
+        framer_frames: usize,
+        opus_frame: usize,
+    ) {
+        self.capture_frames.store(capture_frames, Ordering::Relaxed);
+        self.resampler_frames
+            .store(resampler_frames, Ordering::Relaxed);
+        self.framer_frames.store(framer_frames, Ordering::Relaxed);
+        self.opus_frame.store(opus_frame, Ordering::Relaxed);
+    }
+    pub fn breakdown(&self, rtt_ms: Option<f64>) -> LatencyBreakdown {
+        let source_rate = self.source_rate.load(Ordering::Relaxed).max(1) as f64;
+        let ms =
+            |frames: &AtomicUsize, rate: f64| frames.load(Ordering::Relaxed) as f64 * 1000. / rate;
+        LatencyBreakdown {
+            capture_ms: ms(&self.capture_frames, source_rate),
+            resampler_ms: ms(&self.resampler_frames, source_rate)
+                + ms(&self.resampler_delay, 48_000.),
+            opus_ms: ms(&self.framer_frames, 48_000.)
+                + ms(&self.opus_frame, 48_000.)
+                + ms(&self.opus_lookahead, 48_000.),
+            network_ms: rtt_ms.map(|rtt| rtt / 2.),
+            click_round_trip: *self.click_round_trip.lock().unwrap(),
+        }
+    }
+
+    /// Asks the encode task to put a click into the next block.
+    pub fn request_click(&self) {
+        self.click_requested.store(true, Ordering::Relaxed);
+    }
+    /// Called by the encode task, overwrites the start of `pcm` with a click if one was requested.
+    pub fn inject_click(&self, pcm: &mut [f32], channels: usize) {
+        if !self.click_requested.swap(false, Ordering::Relaxed) {
+            return;
+        }
+        let len = 48;
+        for (i, frame) in pcm.chunks_exact_mut(channels).take(len).enumerate() {
+            frame.fill(0.9 * (1. - i as f32 / len as f32));
+        }
+        *self.click_sent.lock().unwrap() = Some(Instant::now());
+    }
+    /// The receiver reported hearing the click.
+    pub fn click_heard(&self) {
+        if let Some(sent) = self.click_sent.lock().unwrap().take() {
+            *self.click_round_trip.lock().unwrap() = Some(sent.elapsed());
+        }
+    }
+}
+
+/// Where the audio spends its time before it reaches the receiver.
+#[derive(Debug, Clone, Copy)]
+pub struct LatencyBreakdown {
+    pub capture_ms: f64,
+    pub resampler_ms: f64,
+    /// pending samples, one frame and the encoder lookahead
+    pub opus_ms: f64,
+    /// half the round trip time
+    pub network_ms: Option<f64>,
+    /// click injected until the receiver reported it over the control channel
+    pub click_round_trip: Option<Duration>,
+}
+impl LatencyBreakdown {
+    pub fn total_ms(&self) -> f64 {
+        self.capture_ms + self.resampler_ms + self.opus_ms + self.network_ms.unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn breakdown_converts_each_stage() {
+        let probe = LatencyProbe::default();
+        probe.set_stream(44_100, 96, 312);
+        probe.record(441, 882, 480, 960);
+        let breakdown = probe.breakdown(Some(30.));
+        assert!((breakdown.capture_ms - 10.).abs() < 1e-9);
+        // 20 ms buffered at the source rate plus 2 ms filter delay at 48 kHz
+        assert!((breakdown.resampler_ms - 22.).abs() < 1e-9);
+        assert!((breakdown.opus_ms - 36.5).abs() < 1e-9);
+        assert_eq!(breakdown.network_ms, Some(15.));
+        assert!((breakdown.total_ms() - 83.5).abs() < 1e-9);
+    }
+
+    #[test]
+    fn breakdown_without_resampler_or_rtt() {
+        let probe = LatencyProbe::default();
+        probe.set_stream(48_000, 0, 0);
+        probe.record(480, 0, 0, 960);
+        let breakdown = probe.breakdown(None);
+        assert_eq!(breakdown.resampler_ms, 0.);
+        assert_eq!(breakdown.network_ms, None);
+        assert!((breakdown.total_ms() - 30.).abs() < 1e-9);
+    }
+
+    #[test]
+    fn click_is_injected_once_and_timed() {
+        let probe = LatencyProbe::default();
+        let mut pcm = vec![0.; 200];
+        probe.inject_click(&mut pcm, 2);
+        assert!(pcm.iter().all(|&s| s == 0.));
+
+        probe.request_click();
+        probe.inject_click(&mut pcm, 2);
+        assert_eq!(pcm[..2], [0.9, 0.9]);
+        assert!(pcm[2] < 0.9 && pcm[2] > 0.);
+        assert!(pcm[96..].iter().all(|&s| s == 0.));
+
+        std::thread::sleep(Duration::from_millis(5));
+        probe.click_heard();
+        let round_trip = probe.breakdown(None).click_round_trip.unwrap();
+        assert!(round_trip >= Duration::from_millis(5));
+
+        // a second report without a new click keeps the measurement
+        probe.click_heard();
+        assert_eq!(probe.breakdown(None).click_round_trip, Some(round_trip));
+    }
+}
@@ -0,0 +1,85 @@
+use std::collections::VecDeque;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use log::{Level, LevelFilter};
+
+/// Records kept for the log pane.
+const LOG_PANE_CAPACITY: usize = 500;
+/// Modules shown in the log pane, everything still goes to the log file.
+const LOG_PANE_MODULES: &[&str] = &["connection", "resampler", "net"];
+
+#[derive(Debug, Clone)]
+pub struct LogRecord {
+    pub level: Level,
+    /// module path without the crate name
+    pub module: String,
+    pub message: String,
+}
+
+/// Most recent records for the log pane, oldest first.
+#[derive(Debug, Clone, Default)]
+pub struct LogBuffer(Arc<Mutex<VecDeque<LogRecord>>>);
+impl LogBuffer {
+    fn push(&self, record: LogRecord) {
+        let mut records = self.0.lock().unwrap();
+        if records.len() == LOG_PANE_CAPACITY {
+            records.pop_front();
+        }
+        records.push_back(record);
+    }
+    pub fn records(&self) -> Vec<LogRecord> {
+        self.0.lock().unwrap().iter().cloned().collect()
+    }
+}
+
+/// Installs the global logger. Nothing is written to the terminal, it belongs to the TUI.
+pub fn init(level: &str, file: Option<&Path>) -> anyhow::Result<LogBuffer> {
+    let level = level
+        .parse::<LevelFilter>()
+        .map_err(|_| anyhow::anyhow!("invalid log level {level}"))?;
+    let buffer = LogBuffer::default();
+
+    let pane = {
+        let buffer = buffer.clone();
+        fern::Dispatch::new()
+            .filter(|metadata| pane_module(metadata.target()).is_some())
+            .chain(fern::Output::call(move |record| {
+                buffer.push(LogRecord {
+                    level: record.level(),
+                    module: pane_module(record.target()).unwrap_or_default().to_string(),
+                    message: record.args().to_string(),
+                })
+            }))
+    };
+    let mut dispatch = fern::Dispatch::new().level(level).chain(pane);
+    if let Some(file) = file {
+        dispatch = dispatch.chain(
+            fern::Dispatch::new()
+                .format(|out, message, record| {
+                    let now = SystemTime::now()
+                        .duration_since(UNIX_EPOCH)
+                        .unwrap_or_default();
+                    out.finish(format_args!(
+                        "{}.{:03} {:<5} {}: {}",
+                        now.as_secs(),
+                        now.subsec_millis(),
+                        record.level(),
+                        record.target(),
+                        message
+                    ))
+                })
+                .chain(fern::log_file(file)?),
+        );
+    }
+    dispatch.apply()?;
+    Ok(buffer)
+}
+
+/// `audio_share::net` -> `net`, `None` for modules the pane doesn't show.
+fn pane_module(target: &str) -> Option<&str> {
+    let module = target.strip_prefix(concat!(env!("CARGO_CRATE_NAME"), "::"))?;
+    let module = module.split("::").next()?;
+    LOG_PANE_MODULES.contains(&module).then_some(module)
+}
@@ -1,16 +1,43 @@
 pub mod app;
-pub mod app_n;
+pub mod config;
 pub mod connection;
+pub mod control;
+pub mod encoder;
+pub mod file_signaling;
+#[cfg(feature = "slint")]
+pub mod gui;
+pub mod headless;
+pub mod keymap;
+pub mod latency;
+pub mod logging;
+pub mod metadata;
 pub mod net;
+pub mod offline;
+pub mod ogg;
+pub mod reconnect;
 pub mod resampler;
+pub mod sdp;
+pub mod session;
+pub mod signal;
+pub mod source;
+pub mod stats;
+#[cfg(feature = "tui")]
+pub mod tui;
+#[cfg(feature = "tui")]
 pub mod ui;
 
+use std::path::PathBuf;
+
 use cpal::{Device, InputCallbackInfo, traits::DeviceTrait};
 
 use crate::app::App;
+use crate::config::{CONFIG_PATH, Config};
+use crate::headless::Headless;
 
+/// Captures from `device`, errors of the running stream are sent to `errors`.
 pub fn create_stream(
     device: &Device,
+    errors: tokio::sync::mpsc::UnboundedSender<cpal::StreamError>,
 ) -> anyhow::Result<(
     cpal::Stream,
     tokio::sync::broadcast::Receiver<Vec<f32>>,
@@ -24,13 +51,12 @@ pub fn create_stream(
     let stream = device.build_input_stream(
         &config.config(),
         move |data: &[f32], _: &InputCallbackInfo| {
-            // react to stream events and read or write stream data here.
-            // dbg!(send.receiver_count());
-            send.send(data.to_vec()).unwrap();
+            // nobody listening isn't an error, the data is just dropped
+            let _ = send.send(data.to_vec());
         },
         move |err| {
-            // react to errors here.
-            panic!("{}", err);
+            // the app is gone when nobody receives it
+            let _ = errors.send(err);
         },
         None,
     )?;
@@ -38,12 +64,68 @@ pub fn create_stream(
     Ok((stream, recv, sender))
 }
 
+/// `encode --input <in.wav> --output <out.wav|out.opus> [--resampler <kind>]`
+fn run_encode(args: &[String], config: &Config) -> anyhow::Result<()> {
+    let mut input = None;
+    let mut output = None;
+    let mut resampler = config.resampler;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| anyhow::anyhow!("missing value for {arg}"))
+        };
+        match arg.as_str() {
+            "--input" | "-i" => input = Some(PathBuf::from(value()?)),
+            "--output" | "-o" => output = Some(PathBuf::from(value()?)),
+            "--resampler" => resampler = value()?.parse()?,
+            _ => anyhow::bail!("unknown argument {arg}"),
+        }
+    }
+    let (Some(input), Some(output)) = (input, output) else {
+        anyhow::bail!("usage: audio_share encode --input <in.wav> --output <out.wav|out.opus>");
+    };
+    offline::encode_file(&input, &output, resampler)
+}
+
+#[cfg(feature = "tui")]
+async fn run_tui(app: &mut App, logs: logging::LogBuffer) -> anyhow::Result<()> {
+    let keymap = app.config().keys.clone();
+    let mut tui = tui::Tui::new(ratatui::init(), logs, keymap);
+    // restore before the error is printed, the panic hook from `init` covers panics
+    let result = app.run(&mut tui).await;
+    ratatui::restore();
+    result
+}
+
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    let mut app_result = App::new().await?;
-    app_result.scan_devices()?;
-    let mut terminal = ratatui::init();
-    app_result.run(&mut terminal).await?;
-    ratatui::restore();
-    Ok(())
+    let config = Config::load(CONFIG_PATH)?;
+    let logs = logging::init(&config.log_level, config.log_file.as_deref())?;
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    let command = args.first().map(String::as_str);
+    if command == Some("encode") {
+        return run_encode(&args[1..], &config);
+    }
+    let mut app = App::new(config).await?;
+    app.scan_devices()?;
+    // the GUI when built with it, `tui` and `headless` pick the others
+    match command {
+        Some("headless") => app.run(&mut Headless::default()).await,
+        #[cfg(feature = "tui")]
+        Some("tui") => run_tui(&mut app, logs).await,
+        #[cfg(feature = "slint")]
+        _ => {
+            // the window has no log pane
+            drop(logs);
+            gui::run(app)
+        }
+        #[cfg(all(feature = "tui", not(feature = "slint")))]
+        _ => run_tui(&mut app, logs).await,
+        #[cfg(not(any(feature = "tui", feature = "slint")))]
+        _ => {
+            drop(logs);
+            app.run(&mut Headless::default()).await
+        }
+    }
 }
@@ -0,0 +1,180 @@
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::watch;
+
+/// Separates the fields in the `playerctl` output, unlikely to show up in a title.
+const FIELD_SEPARATOR: char = '\u{1f}';
+/// How often [`poll_mpris`] asks `playerctl` for the current track.
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+/// A hanging `playerctl` is killed after this long.
+const PLAYERCTL_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// What is being shared, sent to receivers whenever it changes.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct StreamMetadata {
+    pub title: Option<String>,
+    pub device: Option<String>,
+    pub track: Option<TrackInfo>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct TrackInfo {
+    pub artist: Option<String>,
+    pub title: Option<String>,
+    pub album: Option<String>,
+}
+impl std::fmt::Display for TrackInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match (&self.artist, &self.title) {
+            (Some(artist), Some(title)) => write!(f, "{artist} - {title}"),
+            (None, Some(title)) => f.write_str(title),
+            (Some(artist), None) => f.write_str(artist),
+            (None, None) => f.write_str("Unknown"),
+        }
+    }
+}
+
+/// Reads the current track of the active MPRIS player on the session bus through `playerctl`.
+/// `player` limits the lookup to one player, e.g. `spotify`. Returns `None` when nothing is
+/// playing or `playerctl` isn't installed.
+pub async fn read_mpris(player: Option<&str>) -> Option<TrackInfo> {
+    let mut command = tokio::process::Command::new("playerctl");
+    if let Some(player) = player {
+        command.arg("--player").arg(player);
+    }
+    let format = ["{{artist}}", "{{title}}", "{{album}}"].join(&FIELD_SEPARATOR.to_string());
+    let output = command
+        .args(["metadata", "--format", &format])
+        .kill_on_drop(true)
+        .output()
+        .await
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    parse_playerctl(&String::from_utf8_lossy(&output.stdout))
+}
+
+/// Parses `artist`, `title` and `album` separated by [`FIELD_SEPARATOR`], as `playerctl`
+/// prints them. Empty fields are missing, `None` when all are.
+fn parse_playerctl(output: &str) -> Option<TrackInfo> {
+    let mut fields = output
+        .trim_end_matches('\n')
+        .split(FIELD_SEPARATOR)
+        .map(|f| Some(f.to_string()).filter(|f| !f.is_empty()));
+    let track = TrackInfo {
+        artist: fields.next().flatten(),
+        title: fields.next().flatten(),
+        album: fields.next().flatten(),
+    };
+    (track != TrackInfo::default()).then_some(track)
+}
+
+/// Keeps `tracks` up to date with [`read_mpris`] while `active` is set, so the caller never
+/// waits for `playerctl`. Ends once the caller drops either channel.
+pub async fn poll_mpris(
+    player: Option<String>,
+    mut active: watch::Receiver<bool>,
+    tracks: watch::Sender<Option<TrackInfo>>,
+) {
+    let mut interval = tokio::time::interval(POLL_INTERVAL);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    loop {
+        if active.wait_for(|active| *active).await.is_err() {
+            return;
+        }
+        interval.tick().await;
+        let track = tokio::time::timeout(PLAYERCTL_TIMEOUT, read_mpris(player.as_deref()))
+            .await
+            .unwrap_or_else(|_| {
+                log::warn!("playerctl didn't answer within {PLAYERCTL_TIMEOUT:?}");
+                None
+            });
+        tracks.send_if_modified(|current| {
+            let changed = *current != track;
+            *current = track;
+            changed
+        });
+        if tracks.is_closed() {
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::control::ControlMessage;
+
+    fn track(artist: Option<&str>, title: Option<&str>, album: Option<&str>) -> TrackInfo {
+        TrackInfo {
+            artist: artist.map(str::to_string),
+            title: title.map(str::to_string),
+            album: album.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn parses_playerctl_output() {
+        assert_eq!(
+            parse_playerctl("Daft Punk\u{1f}One More Time\u{1f}Discovery\n"),
+            Some(track(
+                Some("Daft Punk"),
+                Some("One More Time"),
+                Some("Discovery")
+            ))
+        );
+    }
+
+    #[test]
+    fn empty_fields_are_missing() {
+        assert_eq!(
+            parse_playerctl("\u{1f}Radio stream\u{1f}\n"),
+            Some(track(None, Some("Radio stream"), None))
+        );
+        assert_eq!(parse_playerctl("\u{1f}\u{1f}\n"), None);
+        assert_eq!(parse_playerctl(""), None);
+    }
+
+    #[test]
+    fn missing_album_field() {
+        // older players leave the last separator out
+        assert_eq!(
+            parse_playerctl("Artist\u{1f}Title"),
+            Some(track(Some("Artist"), Some("Title"), None))
+        );
+    }
+
+    #[test]
+    fn trailing_newlines_are_dropped() {
+        assert_eq!(
+            parse_playerctl("Artist\u{1f}Two\nLines\u{1f}Album\n\n"),
+            Some(track(Some("Artist"), Some("Two\nLines"), Some("Album")))
+        );
+    }
+
+    #[test]
+    fn metadata_json_on_the_control_channel() {
+        let message = ControlMessage::Metadata(StreamMetadata {
+            title: Some("Living room".to_string()),
+            device: Some("Line in".to_string()),
+            track: Some(track(Some("Artist"), Some("Title"), None)),
+        });
+        let json = serde_json::to_value(&message).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "type": "metadata",
+                "title": "Living room",
+                "device": "Line in",
+                "track": { "artist": "Artist", "title": "Title", "album": null },
+            })
+        );
+        let empty = ControlMessage::Metadata(StreamMetadata::default());
+        assert_eq!(
+            serde_json::to_string(&empty).unwrap(),
+            r#"{"type":"metadata","title":null,"device":null,"track":null}"#
+        );
+    }
+}
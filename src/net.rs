@@ -1,11 +1,34 @@
-use tokio::{net::TcpStream, select};
+use std::collections::{HashMap, VecDeque};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
-use crate::app::App;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{mpsc, oneshot};
+
+/// Largest packet accepted, descriptions are a few kilobytes.
+const MAX_PACKET_SIZE: u32 = 64 * 1024;
+/// Length of the HMAC-SHA256 tag in front of offers and answers.
+const TAG_SIZE: usize = 32;
+/// Time the peer gets for each packet, so idle connections don't pile up.
+const READ_TIMEOUT: Duration = Duration::from_secs(10);
+/// Failed offers from one host before it is locked out.
+const MAX_FAILED_ATTEMPTS: u32 = 5;
+/// How long a host's offers are refused once it is locked out, failures older than this are
+/// forgotten.
+const LOCKOUT: Duration = Duration::from_secs(30);
+/// Rejected offers kept for the UI, older ones are only counted.
+const RECENT_FAILURES: usize = 10;
 
 #[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 enum PacketType {
     Offer,
     Answer,
+    Rejected,
 }
 impl TryFrom<u8> for PacketType {
     type Error = anyhow::Error;
@@ -14,61 +37,415 @@ impl TryFrom<u8> for PacketType {
         match value {
             0 => Ok(PacketType::Offer),
             1 => Ok(PacketType::Answer),
-            _ => Err(anyhow::anyhow!("TODO")),
+            2 => Ok(PacketType::Rejected),
+            _ => Err(anyhow::anyhow!("unknown packet type {value}")),
         }
     }
 }
 
+/// `type: u8`, `size: u32` little endian, then `size` bytes of data.
+/// Offers and answers carry an HMAC-SHA256 tag of the description before the description itself.
 struct Packet {
     r#type: PacketType,
     size: u32,
     data: Vec<u8>,
 }
+impl Packet {
+    fn new(r#type: PacketType, data: Vec<u8>) -> Self {
+        Self {
+            r#type,
+            size: data.len() as u32,
+            data,
+        }
+    }
+    async fn read(stream: &mut TcpStream) -> anyhow::Result<Self> {
+        tokio::time::timeout(READ_TIMEOUT, Self::read_untimed(stream))
+            .await
+            .map_err(|_| anyhow::anyhow!("no packet within {}s", READ_TIMEOUT.as_secs()))?
+    }
+    async fn read_untimed(stream: &mut TcpStream) -> anyhow::Result<Self> {
+        let t = stream.read_u8().await?;
+        let size = stream.read_u32_le().await?;
+        if size > MAX_PACKET_SIZE {
+            anyhow::bail!("packet of {size} bytes is too large");
+        }
+        let mut data = vec![0; size as usize];
+        stream.read_exact(&mut data).await?;
+        Ok(Self {
+            r#type: t.try_into()?,
+            size,
+            data,
+        })
+    }
+    async fn write(&self, stream: &mut TcpStream) -> anyhow::Result<()> {
+        stream.write_u8(self.r#type as u8).await?;
+        stream.write_u32_le(self.size).await?;
+        stream.write_all(&self.data).await?;
+        Ok(())
+    }
+}
+
+/// Shared secret offers have to be signed with, either from the config or a generated PIN.
+/// A host sending too many failed offers is locked out for a while, others can still pair.
+///
+/// The PIN only keeps out hosts that never saw a signed offer. Anyone who captures one on the
+/// network can try all million PINs against its tag offline in well under a second, the
+/// lockout doesn't help against that. Configure a long `pairing_secret` on untrusted networks.
+pub struct PairingSecret {
+    generated: bool,
+    state: Mutex<PairingState>,
+}
+struct PairingState {
+    secret: String,
+    /// hosts with recent failed offers
+    peers: HashMap<IpAddr, PeerFailures>,
+}
+struct PeerFailures {
+    /// failed offers since the last success or lockout
+    failed: u32,
+    last_failure: Instant,
+    locked_until: Option<Instant>,
+}
+impl PairingSecret {
+    pub fn new(configured: Option<String>) -> Self {
+        let generated = configured.is_none();
+        Self {
+            generated,
+            state: Mutex::new(PairingState {
+                secret: configured.unwrap_or_else(generate_pin),
+                peers: HashMap::new(),
+            }),
+        }
+    }
+    /// The PIN to show in the UI, `None` when the secret comes from the config.
+    pub fn pin(&self) -> Option<String> {
+        self.generated.then(|| self.state().secret.clone())
+    }
+    fn state(&self) -> std::sync::MutexGuard<'_, PairingState> {
+        self.state.lock().expect("pairing state poisoned")
+    }
+    fn sign(&self, data: &[u8]) -> Vec<u8> {
+        let mut mac = mac(&self.state().secret);
+        mac.update(data);
+        let mut signed = mac.finalize().into_bytes().to_vec();
+        signed.extend_from_slice(data);
+        signed
+    }
+    /// Splits off and checks the tag, returning the signed data.
+    fn verify<'a>(&self, signed: &'a [u8]) -> Option<&'a [u8]> {
+        verify(&self.state().secret, signed)
+    }
+    /// Like [`PairingSecret::verify`] for offers from unknown peers, failures count towards
+    /// the lockout of `peer`.
+    fn authenticate<'a>(&self, peer: IpAddr, signed: &'a [u8]) -> anyhow::Result<&'a [u8]> {
+        let mut state = self.state();
+        let now = Instant::now();
+        // hosts that stopped trying are forgotten
+        state.peers.retain(|_, p| {
+            p.locked_until.is_some_and(|until| now < until) || now - p.last_failure < LOCKOUT
+        });
+        if let Some(until) = state.peers.get(&peer).and_then(|p| p.locked_until)
+            && now < until
+        {
+            anyhow::bail!(
+                "too many failed attempts, try again in {}s",
+                (until - now).as_secs() + 1
+            );
+        }
+        if let Some(data) = verify(&state.secret, signed) {
+            state.peers.remove(&peer);
+            return Ok(data);
+        }
+        let failures = state.peers.entry(peer).or_insert(PeerFailures {
+            failed: 0,
+            last_failure: now,
+            locked_until: None,
+        });
+        failures.failed += 1;
+        failures.last_failure = now;
+        if failures.failed >= MAX_FAILED_ATTEMPTS {
+            failures.failed = 0;
+            failures.locked_until = Some(now + LOCKOUT);
+            log::warn!(
+                "{peer} locked out for {}s after {MAX_FAILED_ATTEMPTS} failed offers",
+                LOCKOUT.as_secs()
+            );
+        }
+        anyhow::bail!("authentication failed")
+    }
+}
+
+fn generate_pin() -> String {
+    format!("{:06}", rand::random_range(0..1_000_000))
+}
+fn mac(secret: &str) -> Hmac<Sha256> {
+    Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("hmac takes any key size")
+}
+fn verify<'a>(secret: &str, signed: &'a [u8]) -> Option<&'a [u8]> {
+    if signed.len() < TAG_SIZE {
+        return None;
+    }
+    let (tag, data) = signed.split_at(TAG_SIZE);
+    let mut mac = mac(secret);
+    mac.update(data);
+    mac.verify_slice(tag).ok()?;
+    Some(data)
+}
+
+/// Offers on the signaling port that failed authentication or were malformed.
+/// Only the latest [`RECENT_FAILURES`] are kept, older ones are just counted.
+#[derive(Default)]
+pub struct FailedPairings {
+    count: usize,
+    recent: VecDeque<(SocketAddr, String)>,
+}
+impl FailedPairings {
+    pub fn push(&mut self, addr: SocketAddr, reason: String) {
+        self.count += 1;
+        if self.recent.len() == RECENT_FAILURES {
+            self.recent.pop_front();
+        }
+        self.recent.push_back((addr, reason));
+    }
+    pub fn count(&self) -> usize {
+        self.count
+    }
+    pub fn last(&self) -> Option<&(SocketAddr, String)> {
+        self.recent.back()
+    }
+}
 
+pub enum NetEvent {
+    /// An authenticated offer, answer through `reply` with the encoded answer.
+    /// The connection stays open afterwards, ICE restart offers sent to `restarts` are
+    /// forwarded to the receiver and come back as [`NetEvent::RestartAnswer`].
+    Offer {
+        addr: SocketAddr,
+        offer: String,
+        reply: oneshot::Sender<anyhow::Result<String>>,
+        restarts: mpsc::UnboundedSender<String>,
+    },
+    RestartAnswer {
+        addr: SocketAddr,
+        answer: String,
+    },
+    Rejected {
+        addr: SocketAddr,
+        reason: String,
+    },
+}
+
+/// Accepts signaling connections and forwards authenticated offers to the app.
 pub struct NetHandler {
-    listener: tokio::net::TcpListener,
-    stream: Option<TcpStream>,
+    listener: TcpListener,
+    secret: Arc<PairingSecret>,
+    events: mpsc::UnboundedSender<NetEvent>,
 }
 impl NetHandler {
-    pub async fn process_events(&mut self, app: &mut App) -> anyhow::Result<()> {
-        select! {
-            Ok((stream, addr)) = self.listener.accept() => {
-                if self.stream.is_some() {
-                    todo!()
+    pub fn new(
+        listener: TcpListener,
+        secret: Arc<PairingSecret>,
+        events: mpsc::UnboundedSender<NetEvent>,
+    ) -> Self {
+        Self {
+            listener,
+            secret,
+            events,
+        }
+    }
+    pub async fn run(self) {
+        while let Ok((stream, addr)) = self.listener.accept().await {
+            let secret = self.secret.clone();
+            let events = self.events.clone();
+            tokio::spawn(async move {
+                match handle_connection(stream, addr, &secret, &events).await {
+                    // the session ends when the receiver hangs up or a new one replaces it
+                    Ok(Some((stream, restarts))) => {
+                        log::info!("{addr}: offer answered");
+                        if let Err(e) = run_session(stream, addr, &secret, &events, restarts).await
+                        {
+                            log::warn!("{addr}: signaling session ended: {e}");
+                        }
+                    }
+                    Ok(None) => log::info!("{addr}: offer declined"),
+                    Err(e) => {
+                        log::warn!("{addr}: offer rejected: {e}");
+                        let _ = events.send(NetEvent::Rejected {
+                            addr,
+                            reason: e.to_string(),
+                        });
+                    }
                 }
-                self.stream = Some(stream);
-            }
-            r = self.stream.as_ref().unwrap().readable() =>{
-                self.handle_new_data(app).await?;
-            }
-        };
-        Ok(())
+            });
+        }
     }
-    async fn handle_new_data(&self, app: &mut App) -> anyhow::Result<()> {
-        let stream = self.stream.as_ref().unwrap();
-        let mut buf = Vec::<u8>::new();
-        let read = stream.try_read(&mut buf)?;
-        if read < 5 {
-            todo!()
-        }
-        let t = u8::from_le_bytes(buf[0..1].try_into().unwrap());
-        buf.drain(0..1);
-        let s = u32::from_le_bytes(buf[0..4].try_into().unwrap());
-        buf.drain(0..4);
-        if s as usize > buf.len() {
-            todo!()
-        }
-        let data = buf.drain(0..s as usize).collect();
-        let packet = Packet {
-            r#type: t.try_into()?,
-            size: s,
-            data,
-        };
-        match packet.r#type {
-            PacketType::Offer => todo!(),
-            PacketType::Answer => todo!(),
+}
+
+/// Authenticates and answers the offer, returns the connection if the offer was accepted.
+async fn handle_connection(
+    mut stream: TcpStream,
+    addr: SocketAddr,
+    secret: &PairingSecret,
+    events: &mpsc::UnboundedSender<NetEvent>,
+) -> anyhow::Result<Option<(TcpStream, mpsc::UnboundedReceiver<String>)>> {
+    let packet = Packet::read(&mut stream).await?;
+    if packet.r#type != PacketType::Offer {
+        anyhow::bail!("expected an offer, got {:?}", packet.r#type);
+    }
+    let offer = match secret.authenticate(addr.ip(), &packet.data) {
+        Ok(offer) => offer,
+        Err(e) => {
+            Packet::new(PacketType::Rejected, e.to_string().into_bytes())
+                .write(&mut stream)
+                .await?;
+            return Err(e);
         }
+    };
+    let (reply, answer) = oneshot::channel();
+    let (restarts, restart_offers) = mpsc::unbounded_channel();
+    events.send(NetEvent::Offer {
+        addr,
+        offer: String::from_utf8(offer.to_vec())?,
+        reply,
+        restarts,
+    })?;
+    match answer.await? {
+        Ok(answer) => {
+            Packet::new(PacketType::Answer, secret.sign(answer.as_bytes()))
+                .write(&mut stream)
+                .await?;
+            Ok(Some((stream, restart_offers)))
+        }
+        Err(e) => {
+            Packet::new(PacketType::Rejected, e.to_string().into_bytes())
+                .write(&mut stream)
+                .await?;
+            Ok(None)
+        }
+    }
+}
 
-        Ok(())
+/// Roles are swapped for ICE restarts, the sender offers and the receiver answers.
+async fn run_session(
+    mut stream: TcpStream,
+    addr: SocketAddr,
+    secret: &PairingSecret,
+    events: &mpsc::UnboundedSender<NetEvent>,
+    mut restart_offers: mpsc::UnboundedReceiver<String>,
+) -> anyhow::Result<()> {
+    while let Some(offer) = restart_offers.recv().await {
+        log::info!("{addr}: sending ICE restart offer");
+        Packet::new(PacketType::Offer, secret.sign(offer.as_bytes()))
+            .write(&mut stream)
+            .await?;
+        let packet = Packet::read(&mut stream).await?;
+        if packet.r#type != PacketType::Answer {
+            anyhow::bail!("expected an answer, got {:?}", packet.r#type);
+        }
+        let answer = secret
+            .verify(&packet.data)
+            .ok_or_else(|| anyhow::anyhow!("answer failed authentication"))?;
+        events.send(NetEvent::RestartAnswer {
+            addr,
+            answer: String::from_utf8(answer.to_vec())?,
+        })?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn verify_returns_the_signed_data() {
+        let secret = PairingSecret::new(Some("secret".to_string()));
+        let signed = secret.sign(b"offer");
+        assert_eq!(secret.verify(&signed), Some(&b"offer"[..]));
+    }
+
+    #[test]
+    fn verify_rejects_a_wrong_secret() {
+        let signed = PairingSecret::new(Some("secret".to_string())).sign(b"offer");
+        let other = PairingSecret::new(Some("other".to_string()));
+        assert_eq!(other.verify(&signed), None);
+        assert_eq!(other.verify(&signed[..TAG_SIZE - 1]), None);
+    }
+
+    #[test]
+    fn verify_rejects_tampered_data() {
+        let secret = PairingSecret::new(Some("secret".to_string()));
+        let mut signed = secret.sign(b"offer");
+        *signed.last_mut().unwrap() ^= 1;
+        assert_eq!(secret.verify(&signed), None);
+    }
+
+    const PEER: IpAddr = IpAddr::V4(std::net::Ipv4Addr::new(192, 168, 1, 23));
+    const OTHER_PEER: IpAddr = IpAddr::V4(std::net::Ipv4Addr::new(192, 168, 1, 42));
+
+    #[test]
+    fn authenticate_locks_after_failed_attempts() {
+        let secret = PairingSecret::new(Some("secret".to_string()));
+        let wrong = PairingSecret::new(Some("guess".to_string())).sign(b"offer");
+        for _ in 0..MAX_FAILED_ATTEMPTS {
+            assert!(secret.authenticate(PEER, &wrong).is_err());
+        }
+        // even the right secret is refused until the lockout ends
+        let signed = secret.sign(b"offer");
+        assert!(secret.authenticate(PEER, &signed).is_err());
+        secret.state().peers.get_mut(&PEER).unwrap().locked_until = Some(Instant::now());
+        assert_eq!(secret.authenticate(PEER, &signed).unwrap(), b"offer");
+    }
+
+    #[test]
+    fn lockout_only_affects_the_failing_peer() {
+        let secret = PairingSecret::new(None);
+        let pin = secret.pin().unwrap();
+        let wrong = PairingSecret::new(Some(format!("x{pin}"))).sign(b"offer");
+        for _ in 0..MAX_FAILED_ATTEMPTS {
+            assert!(secret.authenticate(PEER, &wrong).is_err());
+        }
+        assert!(secret.authenticate(PEER, &secret.sign(b"offer")).is_err());
+        // the PIN stays and other hosts pair as usual
+        assert_eq!(secret.pin().unwrap(), pin);
+        let signed = PairingSecret::new(Some(pin)).sign(b"offer");
+        assert_eq!(secret.authenticate(OTHER_PEER, &signed).unwrap(), b"offer");
+    }
+
+    #[test]
+    fn success_resets_the_failure_count() {
+        let secret = PairingSecret::new(Some("secret".to_string()));
+        let wrong = PairingSecret::new(Some("guess".to_string())).sign(b"offer");
+        let signed = secret.sign(b"offer");
+        for _ in 0..MAX_FAILED_ATTEMPTS - 1 {
+            assert!(secret.authenticate(PEER, &wrong).is_err());
+        }
+        assert!(secret.authenticate(PEER, &signed).is_ok());
+        assert!(secret.authenticate(PEER, &wrong).is_err());
+        assert!(secret.authenticate(PEER, &signed).is_ok());
+    }
+
+    #[test]
+    fn old_failures_are_forgotten() {
+        let secret = PairingSecret::new(Some("secret".to_string()));
+        let wrong = PairingSecret::new(Some("guess".to_string())).sign(b"offer");
+        assert!(secret.authenticate(PEER, &wrong).is_err());
+        assert!(secret.authenticate(OTHER_PEER, &wrong).is_err());
+        secret.state().peers.get_mut(&PEER).unwrap().last_failure -= LOCKOUT;
+        assert!(secret.authenticate(OTHER_PEER, &wrong).is_err());
+        assert!(!secret.state().peers.contains_key(&PEER));
+        assert_eq!(secret.state().peers[&OTHER_PEER].failed, 2);
+    }
+
+    #[test]
+    fn failed_pairings_keep_the_latest() {
+        let mut failed = FailedPairings::default();
+        let addr: SocketAddr = "127.0.0.1:2138".parse().unwrap();
+        for i in 0..RECENT_FAILURES + 5 {
+            failed.push(addr, i.to_string());
+        }
+        assert_eq!(failed.count(), RECENT_FAILURES + 5);
+        assert_eq!(failed.recent.len(), RECENT_FAILURES);
+        assert_eq!(failed.last().unwrap().1, (RECENT_FAILURES + 4).to_string());
     }
 }
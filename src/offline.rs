@@ -0,0 +1,249 @@
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+
+use crate::encoder::OpusFramer;
+use crate::ogg::OggOpusWriter;
+use crate::resampler::{Resampler, ResamplerKind};
+
+/// Frames handed to the pipeline at once, roughly what a capture callback delivers.
+const BLOCK_FRAMES: usize = 480;
+
+/// Runs a wav file through the same resampler and opus framing as a live stream.
+/// `.wav` output stops after resampling, `.opus` output is a full Ogg Opus file. Both line up
+/// with the input whatever the resampler, its delay is dropped from the start and the output
+/// is as long as the input.
+pub fn encode_file(input: &Path, output: &Path, kind: ResamplerKind) -> anyhow::Result<()> {
+    let (spec, samples) = read_wav(input)?;
+    let channels = spec.channels as usize;
+    let mut resampler = if spec.sample_rate == 48_000 {
+        None
+    } else {
+        Some(Resampler::new(
+            spec.sample_rate as usize,
+            48_000,
+            channels,
+            kind,
+        )?)
+    };
+    let blocks = samples.chunks(BLOCK_FRAMES * channels);
+    let input_frames = (samples.len() / channels) as u64;
+    let valid = input_frames * 48_000 / spec.sample_rate as u64;
+    // the resampler's delay is dropped from the start so the output lines up with the input
+    let mut skip = resampler.as_ref().map_or(0, Resampler::output_delay) * channels;
+    let mut resampled = Vec::new();
+
+    match output.extension().and_then(|e| e.to_str()) {
+        Some("wav") => {
+            let mut pcm = Vec::new();
+            for block in blocks {
+                resampled.clear();
+                match resampler.as_mut() {
+                    Some(resampler) => resampler.process_into(block, &mut resampled)?,
+                    None => resampled.extend_from_slice(block),
+                }
+                feed(&mut pcm, &resampled, &mut skip);
+            }
+            if let Some(resampler) = resampler.as_mut() {
+                resampled.clear();
+                resampler.flush(&mut resampled)?;
+                feed(&mut pcm, &resampled, &mut skip);
+            }
+            // the flushed tail runs past the end of the input
+            pcm.resize(valid as usize * channels, 0.);
+            let spec = hound::WavSpec {
+                channels: spec.channels,
+                sample_rate: 48_000,
+                bits_per_sample: 32,
+                sample_format: hound::SampleFormat::Float,
+            };
+            let mut writer = hound::WavWriter::create(output, spec)?;
+            for sample in pcm {
+                writer.write_sample(sample)?;
+            }
+            writer.finalize()?;
+        }
+        Some("opus") => {
+            // the encoder is stereo only, same as for live streams
+            if channels != 2 {
+                anyhow::bail!("opus output needs a stereo input, got {channels} channels");
+            }
+            let mut framer = OpusFramer::new()?;
+            let pre_skip = framer.lookahead()?;
+            let file = BufWriter::new(File::create(output)?);
+            let mut ogg = OggOpusWriter::new(file, 2, spec.sample_rate, pre_skip as u16)?;
+
+            // samples handed to the encoder after the skipped delay
+            let mut fed = 0;
+            for block in blocks {
+                resampled.clear();
+                match resampler.as_mut() {
+                    Some(resampler) => resampler.process_into(block, &mut resampled)?,
+                    None => resampled.extend_from_slice(block),
+                }
+                fed += feed(framer.pcm_mut(), &resampled, &mut skip);
+                let frames = framer.encode()?;
+                write_frames(&mut ogg, &framer, frames)?;
+            }
+            if let Some(resampler) = resampler.as_mut() {
+                resampled.clear();
+                resampler.flush(&mut resampled)?;
+                fed += feed(framer.pcm_mut(), &resampled, &mut skip);
+            }
+            // exactly the input plus the encoder lookahead, so the end of the input makes it
+            // out and only the last page is longer than the valid samples
+            let expected = (valid as usize + pre_skip) * channels;
+            let pcm = framer.pcm_mut();
+            pcm.resize((pcm.len() + expected).saturating_sub(fed), 0.);
+            let frames = framer.finish()?;
+            write_frames(&mut ogg, &framer, frames)?;
+            ogg.finish(Some(valid))?;
+        }
+        _ => anyhow::bail!(
+            "unsupported output {}, expected .wav or .opus",
+            output.display()
+        ),
+    }
+    Ok(())
+}
+
+/// Appends `pcm` to `output` after dropping the first `skip` samples, returns how many
+/// samples were appended.
+fn feed(output: &mut Vec<f32>, pcm: &[f32], skip: &mut usize) -> usize {
+    let skipped = (*skip).min(pcm.len());
+    *skip -= skipped;
+    output.extend_from_slice(&pcm[skipped..]);
+    pcm.len() - skipped
+}
+
+fn write_frames<W: std::io::Write>(
+    ogg: &mut OggOpusWriter<W>,
+    framer: &OpusFramer,
+    frames: Vec<Vec<u8>>,
+) -> anyhow::Result<()> {
+    let samples = framer.frame_size().unwrap_or_default() as u64 / 2;
+    for frame in frames {
+        ogg.write_packet(&frame, samples)?;
+    }
+    Ok(())
+}
+
+/// Reads a whole wav file as interleaved f32 samples.
+pub fn read_wav(path: &Path) -> anyhow::Result<(hound::WavSpec, Vec<f32>)> {
+    let mut reader = hound::WavReader::open(path)?;
+    let spec = reader.spec();
+    let samples = match spec.sample_format {
+        hound::SampleFormat::Float => reader.samples::<f32>().collect::<Result<_, _>>()?,
+        hound::SampleFormat::Int => {
+            let scale = 1. / (1u64 << (spec.bits_per_sample - 1)) as f32;
+            reader
+                .samples::<i32>()
+                .map(|s| s.map(|s| s as f32 * scale))
+                .collect::<Result<_, _>>()?
+        }
+    };
+    Ok((spec, samples))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `(granule, payload)` of every page, one packet per page as the writer produces them.
+    fn read_pages(data: &[u8]) -> Vec<(u64, &[u8])> {
+        let mut pages = Vec::new();
+        let mut rest = data;
+        while !rest.is_empty() {
+            assert_eq!(&rest[..4], b"OggS");
+            let granule = u64::from_le_bytes(rest[6..14].try_into().unwrap());
+            let segments = rest[26] as usize;
+            let size = rest[27..27 + segments]
+                .iter()
+                .map(|s| *s as usize)
+                .sum::<usize>();
+            let start = 27 + segments;
+            pages.push((granule, &rest[start..start + size]));
+            rest = &rest[start + size..];
+        }
+        pages
+    }
+
+    const KINDS: [ResamplerKind; 4] = [
+        ResamplerKind::Sinc,
+        ResamplerKind::Fast,
+        ResamplerKind::SamplerateBest,
+        ResamplerKind::SamplerateLinear,
+    ];
+    /// A bit over a quarter second, not a multiple of any block or frame size.
+    const FRAMES: usize = 12_345;
+
+    /// Writes a 44.1 kHz stereo file with `sample(frame)` on both channels.
+    fn write_input(dir: &Path, sample: impl Fn(usize) -> i16) -> std::path::PathBuf {
+        std::fs::create_dir_all(dir).unwrap();
+        let input = dir.join("in.wav");
+        let spec = hound::WavSpec {
+            channels: 2,
+            sample_rate: 44_100,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let mut writer = hound::WavWriter::create(&input, spec).unwrap();
+        for i in 0..FRAMES {
+            writer.write_sample(sample(i)).unwrap();
+            writer.write_sample(sample(i)).unwrap();
+        }
+        writer.finalize().unwrap();
+        input
+    }
+
+    #[test]
+    fn granules_never_decrease_when_resampling() {
+        let dir = std::env::temp_dir().join(format!("audio_share_offline_{}", std::process::id()));
+        let input = write_input(&dir, |i| ((i as f32 * 0.05).sin() * 8000.) as i16);
+
+        for kind in KINDS {
+            let output = dir.join(format!("{kind:?}.opus"));
+            encode_file(&input, &output, kind).unwrap();
+            let data = std::fs::read(&output).unwrap();
+            let pages = read_pages(&data);
+            let pre_skip = u16::from_le_bytes(pages[0].1[10..12].try_into().unwrap()) as u64;
+            let granules = pages.iter().map(|(g, _)| *g).collect::<Vec<_>>();
+            assert!(
+                granules.windows(2).all(|w| w[0] <= w[1]),
+                "{kind}: granules decrease: {granules:?}"
+            );
+            let valid = FRAMES as u64 * 48_000 / 44_100;
+            assert_eq!(*granules.last().unwrap(), pre_skip + valid, "{kind}");
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn wav_outputs_line_up_across_resamplers() {
+        let dir = std::env::temp_dir().join(format!("audio_share_wav_{}", std::process::id()));
+        // a single click, wherever a resampler puts it is its delay
+        let click = 6_000;
+        let input = write_input(&dir, |i| if i == click { i16::MAX } else { 0 });
+        let expected = click as f64 * 48_000. / 44_100.;
+        let valid = FRAMES * 48_000 / 44_100;
+
+        for kind in KINDS {
+            let output = dir.join(format!("{kind:?}.wav"));
+            encode_file(&input, &output, kind).unwrap();
+            let (spec, samples) = read_wav(&output).unwrap();
+            assert_eq!(spec.sample_rate, 48_000);
+            assert_eq!(samples.len(), valid * 2, "{kind}: length");
+            let peak = samples
+                .chunks_exact(2)
+                .enumerate()
+                .max_by(|(_, a), (_, b)| a[0].abs().total_cmp(&b[0].abs()))
+                .unwrap()
+                .0;
+            assert!(
+                (peak as f64 - expected).abs() <= 2.,
+                "{kind}: click at {peak}, expected {expected:.1}"
+            );
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,186 @@
+use std::io::{self, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const FLAG_BEGIN_OF_STREAM: u8 = 0x02;
+const FLAG_END_OF_STREAM: u8 = 0x04;
+
+/// Writes opus packets into an Ogg Opus stream (RFC 7845), one packet per page.
+pub struct OggOpusWriter<W: Write> {
+    writer: W,
+    serial: u32,
+    sequence: u32,
+    pre_skip: u16,
+    /// 48 kHz samples per channel decoded from every packet so far, pre-skip included
+    granule: u64,
+    /// the last packet is held back so its page can be flagged as end of stream
+    pending: Option<Vec<u8>>,
+}
+impl<W: Write> OggOpusWriter<W> {
+    /// Writes the OpusHead and OpusTags headers. `pre_skip` is the encoder lookahead in
+    /// 48 kHz samples per channel.
+    pub fn new(writer: W, channels: u8, input_sample_rate: u32, pre_skip: u16) -> io::Result<Self> {
+        let serial = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.subsec_nanos() ^ d.as_secs() as u32)
+            .unwrap_or_default();
+        Self::with_serial(writer, serial, channels, input_sample_rate, pre_skip)
+    }
+    fn with_serial(
+        writer: W,
+        serial: u32,
+        channels: u8,
+        input_sample_rate: u32,
+        pre_skip: u16,
+    ) -> io::Result<Self> {
+        let mut w = Self {
+            writer,
+            serial,
+            sequence: 0,
+            pre_skip,
+            granule: 0,
+            pending: None,
+        };
+
+        let mut head = b"OpusHead".to_vec();
+        head.push(1); // version
+        head.push(channels);
+        head.extend_from_slice(&pre_skip.to_le_bytes());
+        head.extend_from_slice(&input_sample_rate.to_le_bytes());
+        head.extend_from_slice(&0i16.to_le_bytes()); // output gain
+        head.push(0); // mapping family: mono/stereo
+        w.write_page(&head, 0, FLAG_BEGIN_OF_STREAM)?;
+
+        let vendor = opus::version();
+        let mut tags = b"OpusTags".to_vec();
+        tags.extend_from_slice(&(vendor.len() as u32).to_le_bytes());
+        tags.extend_from_slice(vendor.as_bytes());
+        tags.extend_from_slice(&0u32.to_le_bytes()); // user comments
+        w.write_page(&tags, 0, 0)?;
+        Ok(w)
+    }
+    /// Queues a packet holding `samples` 48 kHz samples per channel.
+    pub fn write_packet(&mut self, packet: &[u8], samples: u64) -> io::Result<()> {
+        if let Some(previous) = self.pending.take() {
+            self.write_page(&previous, self.granule, 0)?;
+        }
+        self.granule += samples;
+        self.pending = Some(packet.to_vec());
+        Ok(())
+    }
+    /// Writes the final page. With `valid_samples` the end of the stream gets trimmed so
+    /// the padding of the last frame isn't played back.
+    pub fn finish(mut self, valid_samples: Option<u64>) -> io::Result<W> {
+        let packet = self.pending.take().unwrap_or_default();
+        let mut granule = self.granule;
+        if let Some(valid) = valid_samples {
+            granule = granule.min(self.pre_skip as u64 + valid);
+        }
+        self.write_page(&packet, granule, FLAG_END_OF_STREAM)?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+
+    fn write_page(&mut self, packet: &[u8], granule: u64, flags: u8) -> io::Result<()> {
+        // lacing values, a packet that is a multiple of 255 ends with a 0 segment
+        let mut segments = vec![255u8; packet.len() / 255];
+        if !packet.is_empty() {
+            segments.push((packet.len() % 255) as u8);
+        }
+        if segments.len() > 255 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "packet doesn't fit in a single ogg page",
+            ));
+        }
+
+        let mut page = b"OggS".to_vec();
+        page.push(0); // version
+        page.push(flags);
+        page.extend_from_slice(&granule.to_le_bytes());
+        page.extend_from_slice(&self.serial.to_le_bytes());
+        page.extend_from_slice(&self.sequence.to_le_bytes());
+        page.extend_from_slice(&0u32.to_le_bytes()); // checksum, filled in below
+        page.push(segments.len() as u8);
+        page.extend_from_slice(&segments);
+        page.extend_from_slice(packet);
+        let crc = crc32(&page);
+        page[22..26].copy_from_slice(&crc.to_le_bytes());
+
+        self.writer.write_all(&page)?;
+        self.sequence += 1;
+        Ok(())
+    }
+}
+
+/// CRC-32 as used by ogg: polynomial 0x04c11db7, no reflection, zero init and no final xor.
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0u32;
+    for byte in data {
+        crc ^= (*byte as u32) << 24;
+        for _ in 0..8 {
+            crc = if crc & 0x8000_0000 != 0 {
+                (crc << 1) ^ 0x04c1_1db7
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn crc_matches_the_reference() {
+        // CRC-32/CKSUM checks "123456789" as 0x765e7680, it only adds a final xor
+        assert_eq!(crc32(b"123456789"), !0x765e_7680);
+        assert_eq!(crc32(b""), 0);
+    }
+
+    #[test]
+    fn opus_head_page() {
+        let w = OggOpusWriter::with_serial(Vec::new(), 0x1234_5678, 2, 44_100, 312).unwrap();
+        #[rustfmt::skip]
+        let expected = [
+            b'O', b'g', b'g', b'S',
+            0, // version
+            FLAG_BEGIN_OF_STREAM,
+            0, 0, 0, 0, 0, 0, 0, 0, // granule
+            0x78, 0x56, 0x34, 0x12, // serial
+            0, 0, 0, 0, // sequence
+            0x03, 0xae, 0xed, 0x49, // checksum
+            1, 19, // one segment of 19 bytes
+            b'O', b'p', b'u', b's', b'H', b'e', b'a', b'd',
+            1, // version
+            2, // channels
+            0x38, 0x01, // pre-skip 312
+            0x44, 0xac, 0x00, 0x00, // 44100 Hz
+            0, 0, // output gain
+            0, // mapping family
+        ];
+        assert_eq!(&w.writer[..expected.len()], expected);
+        // OpusTags follows as the second page
+        assert_eq!(&w.writer[expected.len()..expected.len() + 4], b"OggS");
+        assert_eq!(w.writer[expected.len() + 18], 1);
+    }
+
+    #[test]
+    fn finish_trims_the_padding() {
+        let mut w = OggOpusWriter::with_serial(Vec::new(), 1, 2, 48_000, 312).unwrap();
+        let headers = w.writer.len();
+        w.write_packet(&[1; 10], 960).unwrap();
+        w.write_packet(&[2; 10], 960).unwrap();
+        let data = w.finish(Some(1500)).unwrap();
+        // the first packet's page, then the last one flagged and trimmed
+        let last = &data[headers + 27 + 1 + 10..];
+        assert_eq!(last[5], FLAG_END_OF_STREAM);
+        assert_eq!(
+            u64::from_le_bytes(last[6..14].try_into().unwrap()),
+            312 + 1500
+        );
+        let page = &data[headers..headers + 27 + 1 + 10];
+        assert_eq!(u64::from_le_bytes(page[6..14].try_into().unwrap()), 960);
+    }
+}
@@ -0,0 +1,143 @@
+use std::time::{Duration, Instant};
+
+use tokio::sync::mpsc;
+use webrtc::peer_connection::peer_connection_state::RTCPeerConnectionState;
+
+use crate::sdp::DescriptionFormat;
+
+/// Time ICE gets to recover from `Disconnected` on its own before it is restarted.
+const DISCONNECTED_GRACE: Duration = Duration::from_secs(5);
+const INITIAL_BACKOFF: Duration = Duration::from_secs(2);
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+pub const MAX_ATTEMPTS: u32 = 8;
+
+/// How the current session was negotiated, restart offers go back the same way.
+pub struct SignalingChannel {
+    pub format: DescriptionFormat,
+    /// the TCP signaling connection, `None` for paste, clipboard and files
+    pub restarts: Option<mpsc::UnboundedSender<String>>,
+}
+
+/// Decides when to restart ICE while the peer connection is down.
+#[derive(Debug, Default)]
+pub struct ReconnectPolicy {
+    attempts: u32,
+    next_attempt: Option<Instant>,
+    gave_up: bool,
+}
+impl ReconnectPolicy {
+    /// Feeds the current state, returns `true` when an ICE restart should be attempted now.
+    pub fn update(&mut self, state: RTCPeerConnectionState) -> bool {
+        match state {
+            RTCPeerConnectionState::Connected => {
+                *self = Self::default();
+                return false;
+            }
+            RTCPeerConnectionState::Disconnected if self.next_attempt.is_none() => {
+                self.next_attempt = Some(Instant::now() + DISCONNECTED_GRACE);
+            }
+            RTCPeerConnectionState::Failed if self.next_attempt.is_none() => {
+                self.next_attempt = Some(Instant::now());
+            }
+            // closed on purpose, or a restart is in progress
+            _ => {}
+        }
+        if self.gave_up || self.next_attempt.is_none_or(|next| next > Instant::now()) {
+            return false;
+        }
+        if self.attempts >= MAX_ATTEMPTS {
+            self.gave_up = true;
+            self.next_attempt = None;
+            return false;
+        }
+        self.attempts += 1;
+        let backoff = INITIAL_BACKOFF * 2u32.pow(self.attempts - 1);
+        // retried then if the connection still isn't back
+        self.next_attempt = Some(Instant::now() + backoff.min(MAX_BACKOFF));
+        true
+    }
+    /// Forgets the previous session.
+    pub fn reset(&mut self) {
+        *self = Self::default();
+    }
+    pub fn attempts(&self) -> u32 {
+        self.attempts
+    }
+    pub fn gave_up(&self) -> bool {
+        self.gave_up
+    }
+    /// Time until the next attempt, `None` when nothing is scheduled.
+    pub fn next_attempt_in(&self) -> Option<Duration> {
+        self.next_attempt
+            .map(|next| next.saturating_duration_since(Instant::now()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Lets the scheduled attempt come due without waiting for it.
+    fn skip_wait(policy: &mut ReconnectPolicy) {
+        policy.next_attempt = policy.next_attempt.map(|_| Instant::now());
+    }
+
+    #[test]
+    fn disconnected_gets_a_grace_period() {
+        let mut policy = ReconnectPolicy::default();
+        assert!(!policy.update(RTCPeerConnectionState::Disconnected));
+        let wait = policy.next_attempt_in().unwrap();
+        assert!(wait > DISCONNECTED_GRACE - Duration::from_secs(1) && wait <= DISCONNECTED_GRACE);
+        skip_wait(&mut policy);
+        assert!(policy.update(RTCPeerConnectionState::Disconnected));
+        assert_eq!(policy.attempts(), 1);
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_the_maximum() {
+        let mut policy = ReconnectPolicy::default();
+        assert!(policy.update(RTCPeerConnectionState::Failed));
+        // nothing more until the backoff is over
+        assert!(!policy.update(RTCPeerConnectionState::Failed));
+        let mut expected = INITIAL_BACKOFF;
+        for attempt in 1..MAX_ATTEMPTS {
+            let wait = policy.next_attempt_in().unwrap();
+            assert!(
+                wait > expected - Duration::from_secs(1) && wait <= expected,
+                "attempt {attempt}: {wait:?}"
+            );
+            skip_wait(&mut policy);
+            assert!(policy.update(RTCPeerConnectionState::Failed));
+            assert_eq!(policy.attempts(), attempt + 1);
+            expected = (expected * 2).min(MAX_BACKOFF);
+        }
+    }
+
+    #[test]
+    fn gives_up_after_max_attempts() {
+        let mut policy = ReconnectPolicy::default();
+        for _ in 0..MAX_ATTEMPTS {
+            skip_wait(&mut policy);
+            assert!(policy.update(RTCPeerConnectionState::Failed));
+        }
+        skip_wait(&mut policy);
+        assert!(!policy.update(RTCPeerConnectionState::Failed));
+        assert!(policy.gave_up());
+        assert_eq!(policy.next_attempt_in(), None);
+        assert!(!policy.update(RTCPeerConnectionState::Failed));
+
+        // a connection that comes back on its own starts over
+        assert!(!policy.update(RTCPeerConnectionState::Connected));
+        assert!(!policy.gave_up());
+        assert_eq!(policy.attempts(), 0);
+        assert!(policy.update(RTCPeerConnectionState::Failed));
+    }
+
+    #[test]
+    fn ignores_closed_and_connecting() {
+        let mut policy = ReconnectPolicy::default();
+        assert!(!policy.update(RTCPeerConnectionState::Closed));
+        assert!(!policy.update(RTCPeerConnectionState::Connecting));
+        assert_eq!(policy.next_attempt_in(), None);
+    }
+}
@@ -1,93 +1,451 @@
-use rubato::{FastFixedIn, Resampler as RubatoResampler};
+use std::{fmt::Display, os::raw::c_long, str::FromStr};
 
-pub struct Resampler<T: rubato::Sample> {
-    inner: FastFixedIn<T>,
-    buffered_pcm: Vec<T>,
+use libsamplerate_sys::{SRC_DATA, SRC_STATE, src_delete, src_new, src_process};
+use rubato::{
+    FastFixedIn, SincFixedIn, SincInterpolationParameters, SincInterpolationType, VecResampler,
+    WindowFunction,
+};
+use samplerate::ConverterType;
+use serde::{Deserialize, Serialize};
+
+/// Resampling backend used by [`Resampler`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ResamplerKind {
+    /// rubato sinc interpolation, best quality
+    Sinc,
+    /// rubato septic polynomial interpolation, low cpu usage
+    #[default]
+    Fast,
+    /// libsamplerate `SRC_SINC_BEST_QUALITY`
+    SamplerateBest,
+    /// libsamplerate `SRC_SINC_MEDIUM_QUALITY`
+    SamplerateMedium,
+    /// libsamplerate `SRC_SINC_FASTEST`
+    SamplerateFastest,
+    /// libsamplerate `SRC_LINEAR`
+    SamplerateLinear,
+}
+impl ResamplerKind {
+    fn converter_type(&self) -> Option<ConverterType> {
+        match self {
+            ResamplerKind::Sinc | ResamplerKind::Fast => None,
+            ResamplerKind::SamplerateBest => Some(ConverterType::SincBestQuality),
+            ResamplerKind::SamplerateMedium => Some(ConverterType::SincMediumQuality),
+            ResamplerKind::SamplerateFastest => Some(ConverterType::SincFastest),
+            ResamplerKind::SamplerateLinear => Some(ConverterType::Linear),
+        }
+    }
+}
+impl FromStr for ResamplerKind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(serde_json::from_value(serde_json::Value::String(
+            s.to_string(),
+        ))?)
+    }
+}
+impl Display for ResamplerKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            ResamplerKind::Sinc => "rubato sinc",
+            ResamplerKind::Fast => "rubato fast",
+            ResamplerKind::SamplerateBest => "libsamplerate best",
+            ResamplerKind::SamplerateMedium => "libsamplerate medium",
+            ResamplerKind::SamplerateFastest => "libsamplerate fastest",
+            ResamplerKind::SamplerateLinear => "libsamplerate linear",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Frames handed to libsamplerate per `src_process` call.
+const SAMPLERATE_CHUNK_FRAMES: usize = 1024;
+
+/// Thin `SRC_STATE` wrapper that converts into a preallocated scratch buffer,
+/// `samplerate::Samplerate` allocates its output on every call.
+struct Src {
+    ptr: *mut SRC_STATE,
+    ratio: f64,
+    channels: usize,
+    scratch: Vec<f32>,
+}
+// SAFETY: the state is only ever touched through `&mut self`, it just isn't `Send`
+// because of the raw pointer.
+unsafe impl Send for Src {}
+impl Src {
+    fn new(converter_type: ConverterType, ratio: f64, channels: usize) -> anyhow::Result<Self> {
+        let mut error = 0;
+        let ptr = unsafe { src_new(converter_type as i32, channels as i32, &mut error) };
+        if ptr.is_null() {
+            return Err(samplerate::Error::from_int(error).into());
+        }
+        let scratch_frames = (SAMPLERATE_CHUNK_FRAMES as f64 * ratio).ceil() as usize + 1;
+        Ok(Self {
+            ptr,
+            ratio,
+            channels,
+            scratch: vec![0.; scratch_frames * channels],
+        })
+    }
+    fn process_into(
+        &mut self,
+        mut data: &[f32],
+        end_of_input: bool,
+        out: &mut Vec<f32>,
+    ) -> anyhow::Result<()> {
+        // at end of input keep going until the filter tail is drained as well
+        loop {
+            let chunk = &data[..data.len().min(SAMPLERATE_CHUNK_FRAMES * self.channels)];
+            let (used, generated) = self.run(chunk, end_of_input && chunk.len() == data.len())?;
+            out.extend_from_slice(&self.scratch[..generated * self.channels]);
+            data = &data[used * self.channels..];
+            if data.is_empty() && (!end_of_input || generated == 0) {
+                return Ok(());
+            }
+        }
+    }
+    /// Returns `(input_frames_used, output_frames_generated)`.
+    fn run(&mut self, input: &[f32], end_of_input: bool) -> anyhow::Result<(usize, usize)> {
+        let mut data = SRC_DATA {
+            data_in: input.as_ptr(),
+            data_out: self.scratch.as_mut_ptr(),
+            input_frames: (input.len() / self.channels) as c_long,
+            output_frames: (self.scratch.len() / self.channels) as c_long,
+            input_frames_used: 0,
+            output_frames_gen: 0,
+            end_of_input: end_of_input as i32,
+            src_ratio: self.ratio,
+        };
+        let error = unsafe { src_process(self.ptr, &mut data) };
+        if error != 0 {
+            return Err(samplerate::Error::from_int(error).into());
+        }
+        Ok((
+            data.input_frames_used as usize,
+            data.output_frames_gen as usize,
+        ))
+    }
+}
+impl Drop for Src {
+    fn drop(&mut self) {
+        unsafe { src_delete(self.ptr) };
+    }
+}
+
+enum Backend {
+    Rubato {
+        inner: Box<dyn VecResampler<f32>>,
+        /// planar input, filled frame by frame until `input_frames_next` frames are ready
+        input: Vec<Vec<f32>>,
+        filled: usize,
+        output: Vec<Vec<f32>>,
+    },
+    Samplerate(Src),
+}
+
+/// Interleaved in, interleaved out resampler. All buffers are allocated in [`Resampler::new`],
+/// so [`Resampler::process_into`] doesn't allocate once `out` has grown to its working size.
+pub struct Resampler {
+    inner: Backend,
+    kind: ResamplerKind,
     channels: usize,
+    /// set by [`Resampler::flush`], the rubato input buffers are truncated then
+    flushed: bool,
 }
-impl<T: rubato::Sample> Resampler<T> {
+impl Resampler {
     pub fn new(
         in_sample_rate: usize,
         out_sample_rate: usize,
         channels: usize,
+        kind: ResamplerKind,
     ) -> anyhow::Result<Self> {
+        log::info!("resampling {in_sample_rate} Hz to {out_sample_rate} Hz with {kind:?}");
         let resample_ratio = out_sample_rate as f64 / in_sample_rate as f64;
-        Ok(Self {
-            inner: rubato::FastFixedIn::new(
+        let rubato: Box<dyn VecResampler<f32>> = match kind {
+            ResamplerKind::Sinc => Box::new(SincFixedIn::<f32>::new(
+                resample_ratio,
+                10.,
+                SincInterpolationParameters {
+                    sinc_len: 256,
+                    f_cutoff: 0.95,
+                    oversampling_factor: 128,
+                    interpolation: SincInterpolationType::Cubic,
+                    window: WindowFunction::BlackmanHarris2,
+                },
+                1024,
+                channels,
+            )?),
+            ResamplerKind::Fast => Box::new(FastFixedIn::<f32>::new(
                 resample_ratio,
                 10.,
                 rubato::PolynomialDegree::Septic,
                 1024,
                 channels,
-            )?,
-            buffered_pcm: Vec::new(),
+            )?),
+            _ => {
+                return Ok(Self {
+                    inner: Backend::Samplerate(Src::new(
+                        kind.converter_type().unwrap(),
+                        resample_ratio,
+                        channels,
+                    )?),
+                    kind,
+                    channels,
+                    flushed: false,
+                });
+            }
+        };
+        Ok(Self {
+            inner: Backend::Rubato {
+                input: rubato.input_buffer_allocate(true),
+                filled: 0,
+                output: rubato.output_buffer_allocate(true),
+                inner: rubato,
+            },
+            kind,
             channels,
+            flushed: false,
         })
     }
-    pub fn process(&mut self, data: &[T]) -> Vec<T> {
-        self.buffered_pcm.extend_from_slice(data);
-        let mut resampled_pcm = Vec::new();
-
-        let chunk_size = 1024 * 2;
-        let full_chunks = self.buffered_pcm.len() / chunk_size;
-        let remainder = self.buffered_pcm.len() % chunk_size;
-        for chunk in 0..full_chunks {
-            let buffered_pcm = &self.buffered_pcm[chunk * chunk_size..(chunk + 1) * chunk_size];
-            let d = deinterleave_audio(&buffered_pcm, self.channels);
-
-            let pcm = self.inner.process(&d, None).unwrap();
-            resampled_pcm.extend_from_slice(&interleave_audio(&pcm));
+    pub fn kind(&self) -> ResamplerKind {
+        self.kind
+    }
+    /// Number of input frames held back until the backend has a full chunk to work on.
+    pub fn buffered_frames(&self) -> usize {
+        match &self.inner {
+            Backend::Rubato { filled, .. } => *filled,
+            Backend::Samplerate(_) => 0,
         }
-        if remainder == 0 {
-            self.buffered_pcm.clear();
-        } else {
-            self.buffered_pcm.copy_within(full_chunks * chunk_size.., 0);
-            self.buffered_pcm.truncate(remainder);
+    }
+    /// Output frames the signal is delayed by, libsamplerate already aligns its output with
+    /// the input.
+    pub fn output_delay(&self) -> usize {
+        match &self.inner {
+            Backend::Rubato { inner, .. } => inner.output_delay(),
+            Backend::Samplerate(_) => 0,
+        }
+    }
+    /// Resamples interleaved `data` and appends the interleaved result to `out`.
+    pub fn process_into(&mut self, data: &[f32], out: &mut Vec<f32>) -> anyhow::Result<()> {
+        if self.flushed {
+            anyhow::bail!("resampler was already flushed");
+        }
+        let (inner, input, filled, output) = match &mut self.inner {
+            Backend::Rubato {
+                inner,
+                input,
+                filled,
+                output,
+            } => (inner, input, filled, output),
+            Backend::Samplerate(src) => return src.process_into(data, false, out),
+        };
+        for frame in data.chunks_exact(self.channels) {
+            for (channel, sample) in input.iter_mut().zip(frame) {
+                channel[*filled] = *sample;
+            }
+            *filled += 1;
+            if *filled == inner.input_frames_next() {
+                let (_, written) = inner.process_into_buffer(input, output, None)?;
+                interleave_into(output, written, out);
+                *filled = 0;
+            }
+        }
+        Ok(())
+    }
+    /// Resamples whatever is still buffered, including the resampler's own delay line.
+    /// Call once when the input stream stops, feeding or flushing it again fails afterwards.
+    pub fn flush(&mut self, out: &mut Vec<f32>) -> anyhow::Result<()> {
+        if self.flushed {
+            anyhow::bail!("resampler was already flushed");
+        }
+        self.flushed = true;
+        let (inner, input, filled, output) = match &mut self.inner {
+            Backend::Rubato {
+                inner,
+                input,
+                filled,
+                output,
+            } => (inner, input, filled, output),
+            Backend::Samplerate(src) => return src.process_into(&[], true, out),
+        };
+        if *filled > 0 {
+            // process_partial takes the frame count from the channel length
+            for channel in input.iter_mut() {
+                channel.truncate(*filled);
+            }
+            let (_, written) = inner.process_partial_into_buffer(Some(input), output, None)?;
+            interleave_into(output, written, out);
+            *filled = 0;
+        }
+        let (_, written) = inner.process_partial_into_buffer(None, output, None)?;
+        interleave_into(output, written, out);
+        Ok(())
+    }
+}
+
+/// Appends the first `frames` frames of planar `channel_data` to `out` as interleaved samples.
+fn interleave_into<T: rubato::Sample>(channel_data: &[Vec<T>], frames: usize, out: &mut Vec<T>) {
+    for sample_idx in 0..frames {
+        for channel in channel_data {
+            out.push(channel[sample_idx]);
         }
-        resampled_pcm
     }
 }
-pub fn deinterleave_audio<T: rubato::Sample>(
-    interleaved_data: &[T],
-    num_channels: usize,
-) -> Vec<Vec<T>> {
-    let samples_per_channel = interleaved_data.len() / num_channels;
 
-    // Create a vector to hold each channel's data
-    let mut channel_data: Vec<Vec<T>> = vec![Vec::with_capacity(samples_per_channel); num_channels];
+#[cfg(test)]
+mod tests {
+    use std::alloc::{GlobalAlloc, Layout, System};
+    use std::cell::Cell;
 
-    // Distribute the interleaved samples to their respective channels
-    for (i, &sample) in interleaved_data.iter().enumerate() {
-        let channel_idx = i % num_channels;
-        channel_data[channel_idx].push(sample);
+    use super::*;
+
+    /// Counts the allocations of the current thread, tests run on their own threads.
+    struct CountingAllocator;
+    thread_local! {
+        static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+    }
+    fn count_allocation() {
+        let _ = ALLOCATIONS.try_with(|count| count.set(count.get() + 1));
     }
+    // SAFETY: forwards to the system allocator, counting needs no allocation itself
+    unsafe impl GlobalAlloc for CountingAllocator {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            count_allocation();
+            unsafe { System.alloc(layout) }
+        }
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            unsafe { System.dealloc(ptr, layout) }
+        }
+        unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+            count_allocation();
+            unsafe { System.realloc(ptr, layout, new_size) }
+        }
+    }
+    #[global_allocator]
+    static ALLOCATOR: CountingAllocator = CountingAllocator;
 
-    channel_data
-}
-pub fn interleave_audio<T: rubato::Sample>(channel_data: &[Vec<T>]) -> Vec<T> {
-    if channel_data.is_empty() {
-        return Vec::new();
+    const KINDS: [ResamplerKind; 4] = [
+        ResamplerKind::Sinc,
+        ResamplerKind::Fast,
+        ResamplerKind::SamplerateMedium,
+        ResamplerKind::SamplerateLinear,
+    ];
+
+    /// Feeds `frames` frames of a constant, different level per channel in uneven blocks
+    /// and flushes, returns the interleaved output.
+    fn resample_levels(kind: ResamplerKind, channels: usize, frames: usize) -> Vec<f32> {
+        let mut resampler = Resampler::new(44_100, 48_000, channels, kind).unwrap();
+        let input = (0..frames * channels)
+            .map(|i| level(i % channels))
+            .collect::<Vec<_>>();
+        let mut out = Vec::new();
+        let mut rest = input.as_slice();
+        for block_frames in [1, 7, 333, 1024, 1500].into_iter().cycle() {
+            if rest.is_empty() {
+                break;
+            }
+            let (block, next) = rest.split_at((block_frames * channels).min(rest.len()));
+            resampler.process_into(block, &mut out).unwrap();
+            rest = next;
+        }
+        resampler.flush(&mut out).unwrap();
+        assert_eq!(resampler.buffered_frames(), 0);
+        out
     }
 
-    let num_channels = channel_data.len();
-    let samples_per_channel = channel_data[0].len();
+    fn level(channel: usize) -> f32 {
+        0.1 * (channel + 1) as f32
+    }
 
-    // Verify all channels have the same number of samples
-    for channel in channel_data.iter() {
-        if channel.len() != samples_per_channel {
-            panic!("All channels must have the same number of samples");
+    fn check_chunking(channels: usize) {
+        let frames = 4_800;
+        for kind in KINDS {
+            let out = resample_levels(kind, channels, frames);
+            assert_eq!(out.len() % channels, 0, "{kind}");
+            // the flush drains everything the input turns into
+            let expected = frames * 48_000 / 44_100;
+            assert!(
+                out.len() / channels + 2 >= expected,
+                "{kind}: {}",
+                out.len()
+            );
+            // channels stay in place, checked away from the filter ramps at both ends
+            let out_frames = out.len() / channels;
+            for frame in out
+                .chunks_exact(channels)
+                .take(out_frames * 3 / 4)
+                .skip(out_frames / 4)
+            {
+                for (channel, sample) in frame.iter().enumerate() {
+                    assert!(
+                        (sample - level(channel)).abs() < 0.01,
+                        "{kind}: channel {channel} is {sample}"
+                    );
+                }
+            }
         }
     }
 
-    // Create the output buffer with enough space for all samples
-    let mut interleaved = Vec::with_capacity(num_channels * samples_per_channel);
+    #[test]
+    fn chunks_mono_input_by_frames() {
+        check_chunking(1);
+    }
+
+    #[test]
+    fn chunks_six_channel_input_by_frames() {
+        check_chunking(6);
+    }
+
+    #[test]
+    fn flush_drains_the_tail() {
+        for kind in KINDS {
+            let mut resampler = Resampler::new(44_100, 48_000, 2, kind).unwrap();
+            // less than a rubato chunk, so nothing comes out before the flush
+            let input = vec![0.5; 1_000 * 2];
+            let mut out = Vec::new();
+            resampler.process_into(&input, &mut out).unwrap();
+            let before = out.len();
+            resampler.flush(&mut out).unwrap();
+            assert!(out.len() > before, "{kind}");
+            assert!(out.len() / 2 + 2 >= 1_000 * 48_000 / 44_100, "{kind}");
+        }
+    }
 
-    // Interleave the samples
-    for sample_idx in 0..samples_per_channel {
-        for channel_idx in 0..num_channels {
-            interleaved.push(channel_data[channel_idx][sample_idx]);
+    #[test]
+    fn fails_after_flush() {
+        for kind in KINDS {
+            let mut resampler = Resampler::new(44_100, 48_000, 2, kind).unwrap();
+            let mut out = Vec::new();
+            resampler.process_into(&[0.1; 300], &mut out).unwrap();
+            resampler.flush(&mut out).unwrap();
+            assert!(
+                resampler.process_into(&[0.1; 300], &mut out).is_err(),
+                "{kind}"
+            );
+            assert!(resampler.flush(&mut out).is_err(), "{kind}");
         }
     }
 
-    interleaved
+    #[test]
+    fn process_into_does_not_allocate_after_warm_up() {
+        for kind in KINDS {
+            let mut resampler = Resampler::new(44_100, 48_000, 2, kind).unwrap();
+            // 10 ms blocks like a capture callback
+            let block = vec![0.25; 441 * 2];
+            // the working size, room for a whole rubato chunk of output
+            let mut out = Vec::with_capacity(block.len() * 4);
+            for _ in 0..10 {
+                out.clear();
+                resampler.process_into(&block, &mut out).unwrap();
+            }
+            let before = ALLOCATIONS.with(Cell::get);
+            for _ in 0..200 {
+                out.clear();
+                resampler.process_into(&block, &mut out).unwrap();
+            }
+            assert_eq!(ALLOCATIONS.with(Cell::get), before, "{kind}");
+        }
+    }
 }
@@ -0,0 +1,377 @@
+//! Text encodings of session descriptions for manual signaling.
+//!
+//! [`DescriptionFormat::Full`] is the original base64 of the `RTCSessionDescription` JSON.
+//! [`DescriptionFormat::Compact`] keeps only what is needed to connect (ICE credentials,
+//! fingerprint, candidates, the opus codec and the data channel section), deflates it and
+//! encodes it as unpadded URL safe base64 behind a `c1.` prefix. Receivers rebuild the SDP with
+//! the same template as `expand`.
+
+use std::io::{Read, Write};
+
+use base64::Engine;
+use base64::prelude::{BASE64_STANDARD, BASE64_URL_SAFE, BASE64_URL_SAFE_NO_PAD};
+use flate2::Compression;
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+use serde::{Deserialize, Serialize};
+use webrtc::peer_connection::sdp::sdp_type::RTCSdpType;
+use webrtc::peer_connection::sdp::session_description::RTCSessionDescription;
+
+pub const COMPACT_PREFIX: &str = "c1.";
+/// Largest inflated compact description, real ones are well below a kilobyte.
+const MAX_DESCRIPTION_SIZE: u64 = 64 * 1024;
+
+/// Media attributes that survive compaction, everything else is left to defaults.
+const KEPT_ATTRIBUTES: &[&str] = &[
+    "rtcp-mux",
+    "rtcp-rsize",
+    "rtpmap:",
+    "fmtp:",
+    "rtcp-fb:",
+    "sendrecv",
+    "sendonly",
+    "recvonly",
+    "inactive",
+    "ssrc:",
+    "msid:",
+    "extmap:",
+    "sctp-port:",
+    "max-message-size:",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DescriptionFormat {
+    Full,
+    Compact,
+}
+
+/// Decodes a pasted description, detecting the format from the prefix.
+pub fn decode(text: &str) -> anyhow::Result<(RTCSessionDescription, DescriptionFormat)> {
+    let text = text.trim();
+    if let Some(compact) = text.strip_prefix(COMPACT_PREFIX) {
+        let deflated = BASE64_URL_SAFE_NO_PAD.decode(compact)?;
+        let mut json = Vec::new();
+        DeflateDecoder::new(deflated.as_slice())
+            .take(MAX_DESCRIPTION_SIZE + 1)
+            .read_to_end(&mut json)?;
+        if json.len() as u64 > MAX_DESCRIPTION_SIZE {
+            anyhow::bail!("description is larger than {MAX_DESCRIPTION_SIZE} bytes");
+        }
+        let compact = serde_json::from_slice::<CompactDescription>(&json)?;
+        return Ok((expand(&compact)?, DescriptionFormat::Compact));
+    }
+    let json = BASE64_URL_SAFE.decode(text)?;
+    let desc = serde_json::from_slice::<RTCSessionDescription>(&json)?;
+    Ok((desc, DescriptionFormat::Full))
+}
+
+pub fn encode(desc: &RTCSessionDescription, format: DescriptionFormat) -> anyhow::Result<String> {
+    match format {
+        DescriptionFormat::Full => Ok(BASE64_STANDARD.encode(serde_json::to_string(desc)?)),
+        DescriptionFormat::Compact => {
+            let json = serde_json::to_vec(&compact(desc)?)?;
+            let mut encoder = DeflateEncoder::new(Vec::new(), Compression::best());
+            encoder.write_all(&json)?;
+            let deflated = encoder.finish()?;
+            Ok(COMPACT_PREFIX.to_string() + &BASE64_URL_SAFE_NO_PAD.encode(deflated))
+        }
+    }
+}
+
+/// Short field names, the JSON is deflated but every byte still ends up in the QR code.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct CompactDescription {
+    #[serde(rename = "t")]
+    sdp_type: RTCSdpType,
+    #[serde(rename = "u")]
+    ufrag: String,
+    #[serde(rename = "p")]
+    pwd: String,
+    /// `sha-256 AB:CD:..` without the colons
+    #[serde(rename = "f")]
+    fingerprint: String,
+    #[serde(rename = "s")]
+    setup: String,
+    #[serde(rename = "m")]
+    media: Vec<CompactMedia>,
+    /// `a=candidate:` values, all media are bundled on the first transport
+    #[serde(rename = "c")]
+    candidates: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct CompactMedia {
+    /// `audio` or `application`
+    #[serde(rename = "k")]
+    kind: String,
+    #[serde(rename = "i")]
+    mid: String,
+    #[serde(rename = "p")]
+    protocol: String,
+    /// payload types, or `webrtc-datachannel`
+    #[serde(rename = "f")]
+    formats: Vec<String>,
+    /// attribute lines without `a=`
+    #[serde(rename = "a")]
+    attributes: Vec<String>,
+}
+
+fn compact(desc: &RTCSessionDescription) -> anyhow::Result<CompactDescription> {
+    let mut compact = CompactDescription {
+        sdp_type: desc.sdp_type,
+        ufrag: String::new(),
+        pwd: String::new(),
+        fingerprint: String::new(),
+        setup: String::new(),
+        media: Vec::new(),
+        candidates: Vec::new(),
+    };
+    for line in desc.sdp.lines() {
+        if let Some(media) = line.strip_prefix("m=") {
+            let mut fields = media.split(' ');
+            let kind = fields.next().unwrap_or_default().to_string();
+            let protocol = fields.nth(1).unwrap_or_default().to_string();
+            compact.media.push(CompactMedia {
+                kind,
+                mid: String::new(),
+                protocol,
+                formats: fields.map(str::to_string).collect(),
+                attributes: Vec::new(),
+            });
+            continue;
+        }
+        let Some(attribute) = line.strip_prefix("a=") else {
+            continue;
+        };
+        let (name, value) = attribute.split_once(':').unwrap_or((attribute, ""));
+        match name {
+            "ice-ufrag" => compact.ufrag = value.to_string(),
+            "ice-pwd" => compact.pwd = value.to_string(),
+            "fingerprint" => compact.fingerprint = value.replace(':', ""),
+            "setup" => compact.setup = value.to_string(),
+            "candidate" if !compact.candidates.iter().any(|c| c == value) => {
+                compact.candidates.push(value.to_string())
+            }
+            _ => {
+                let Some(media) = compact.media.last_mut() else {
+                    continue;
+                };
+                if name == "mid" {
+                    media.mid = value.to_string();
+                } else if KEPT_ATTRIBUTES.iter().any(|kept| {
+                    attribute == *kept || (kept.ends_with(':') && attribute.starts_with(kept))
+                }) {
+                    media.attributes.push(attribute.to_string());
+                }
+            }
+        }
+    }
+    if compact.ufrag.is_empty() || compact.fingerprint.is_empty() {
+        anyhow::bail!("description has no ICE credentials or fingerprint");
+    }
+    // only opus is ever sent, drop the other codecs and their attributes
+    for media in compact.media.iter_mut().filter(|m| m.kind == "audio") {
+        let opus = media
+            .attributes
+            .iter()
+            .filter_map(|a| a.strip_prefix("rtpmap:"))
+            .find(|a| a.to_lowercase().contains("opus/48000"))
+            .and_then(|a| a.split(' ').next())
+            .map(str::to_string);
+        if let Some(opus) = opus {
+            media.formats.retain(|f| *f == opus);
+            media.attributes.retain(|a| match a.split_once(':') {
+                Some(("rtpmap" | "fmtp" | "rtcp-fb", value)) => {
+                    value.split(' ').next() == Some(opus.as_str())
+                }
+                _ => true,
+            });
+        }
+    }
+    Ok(compact)
+}
+
+/// Rebuilds a full SDP from the compact form.
+fn expand(compact: &CompactDescription) -> anyhow::Result<RTCSessionDescription> {
+    let (algorithm, hex) = compact
+        .fingerprint
+        .split_once(' ')
+        .ok_or_else(|| anyhow::anyhow!("malformed fingerprint"))?;
+    let hex = hex
+        .as_bytes()
+        .chunks(2)
+        .map(|c| String::from_utf8_lossy(c))
+        .collect::<Vec<_>>();
+    let fingerprint = format!("{algorithm} {}", hex.join(":"));
+    let mids = compact
+        .media
+        .iter()
+        .map(|m| m.mid.as_str())
+        .collect::<Vec<_>>();
+
+    let mut sdp = vec![
+        "v=0".to_string(),
+        "o=- 0 2 IN IP4 127.0.0.1".to_string(),
+        "s=-".to_string(),
+        "t=0 0".to_string(),
+        format!("a=fingerprint:{fingerprint}"),
+        format!("a=group:BUNDLE {}", mids.join(" ")),
+    ];
+    for (i, media) in compact.media.iter().enumerate() {
+        sdp.push(format!(
+            "m={} 9 {} {}",
+            media.kind,
+            media.protocol,
+            media.formats.join(" ")
+        ));
+        sdp.push("c=IN IP4 0.0.0.0".to_string());
+        sdp.push(format!("a=ice-ufrag:{}", compact.ufrag));
+        sdp.push(format!("a=ice-pwd:{}", compact.pwd));
+        sdp.push(format!("a=fingerprint:{fingerprint}"));
+        sdp.push(format!("a=setup:{}", compact.setup));
+        sdp.push(format!("a=mid:{}", media.mid));
+        sdp.extend(media.attributes.iter().map(|a| format!("a={a}")));
+        if i == 0 {
+            sdp.extend(
+                compact
+                    .candidates
+                    .iter()
+                    .map(|c| format!("a=candidate:{c}")),
+            );
+            sdp.push("a=end-of-candidates".to_string());
+        }
+    }
+    let sdp = sdp.join("\r\n") + "\r\n";
+    Ok(match compact.sdp_type {
+        RTCSdpType::Offer => RTCSessionDescription::offer(sdp)?,
+        RTCSdpType::Answer => RTCSessionDescription::answer(sdp)?,
+        RTCSdpType::Pranswer => RTCSessionDescription::pranswer(sdp)?,
+        other => anyhow::bail!("unsupported description type {other}"),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An offer as webrtc-rs creates it for an audio transceiver and a data channel.
+    const OFFER: &str = "v=0\r
+o=- 3137365340843385233 776934105 IN IP4 0.0.0.0\r
+s=-\r
+t=0 0\r
+a=fingerprint:sha-256 2E:57:1F:6A:E4:3B:79:07:62:46:0C:5F:DB:4A:D3:28:0D:A5:36:40:6F:CE:22:88:E3:B1:0A:74:9E:C1:61:D0\r
+a=extmap-allow-mixed\r
+a=group:BUNDLE 0 1\r
+m=audio 9 UDP/TLS/RTP/SAVPF 111 9 0 8\r
+c=IN IP4 0.0.0.0\r
+a=setup:actpass\r
+a=mid:0\r
+a=ice-ufrag:KgXhNrPkdWvUqLzs\r
+a=ice-pwd:hfKXbRtVYyJmOqNdxCzsWaLpGeUvIiTo\r
+a=rtcp-mux\r
+a=rtcp-rsize\r
+a=rtpmap:111 opus/48000/2\r
+a=fmtp:111 minptime=10;useinbandfec=1\r
+a=rtcp-fb:111 transport-cc \r
+a=rtpmap:9 G722/8000\r
+a=rtcp-fb:9 transport-cc \r
+a=rtpmap:0 PCMU/8000\r
+a=rtpmap:8 PCMA/8000\r
+a=extmap:1 http://www.ietf.org/id/draft-holmer-rmcat-transport-wide-cc-extensions-01\r
+a=recvonly\r
+a=candidate:1966762133 1 udp 2130706431 192.168.1.23 52011 typ host\r
+a=candidate:1966762133 2 udp 2130706431 192.168.1.23 52011 typ host\r
+a=candidate:2231734970 1 udp 1694498815 203.0.113.7 52011 typ srflx raddr 0.0.0.0 rport 52011\r
+a=candidate:2231734970 2 udp 1694498815 203.0.113.7 52011 typ srflx raddr 0.0.0.0 rport 52011\r
+a=end-of-candidates\r
+m=application 9 UDP/DTLS/SCTP webrtc-datachannel\r
+c=IN IP4 0.0.0.0\r
+a=setup:actpass\r
+a=mid:1\r
+a=sendrecv\r
+a=sctp-port:5000\r
+a=ice-ufrag:KgXhNrPkdWvUqLzs\r
+a=ice-pwd:hfKXbRtVYyJmOqNdxCzsWaLpGeUvIiTo\r
+a=candidate:1966762133 1 udp 2130706431 192.168.1.23 52011 typ host\r
+a=candidate:1966762133 2 udp 2130706431 192.168.1.23 52011 typ host\r
+a=candidate:2231734970 1 udp 1694498815 203.0.113.7 52011 typ srflx raddr 0.0.0.0 rport 52011\r
+a=candidate:2231734970 2 udp 1694498815 203.0.113.7 52011 typ srflx raddr 0.0.0.0 rport 52011\r
+a=end-of-candidates\r
+";
+
+    fn offer() -> RTCSessionDescription {
+        RTCSessionDescription::offer(OFFER.to_string()).unwrap()
+    }
+
+    #[test]
+    fn compact_round_trip() {
+        let encoded = encode(&offer(), DescriptionFormat::Compact).unwrap();
+        assert!(encoded.starts_with(COMPACT_PREFIX));
+        let (expanded, format) = decode(&encoded).unwrap();
+        assert_eq!(format, DescriptionFormat::Compact);
+        assert_eq!(expanded.sdp_type, RTCSdpType::Offer);
+        // nothing needed to connect is lost on the way
+        assert_eq!(compact(&expanded).unwrap(), compact(&offer()).unwrap());
+        let lines = expanded.sdp.lines().collect::<Vec<_>>();
+        for line in [
+            "m=audio 9 UDP/TLS/RTP/SAVPF 111",
+            "a=ice-ufrag:KgXhNrPkdWvUqLzs",
+            "a=ice-pwd:hfKXbRtVYyJmOqNdxCzsWaLpGeUvIiTo",
+            "a=fingerprint:sha-256 2E:57:1F:6A:E4:3B:79:07:62:46:0C:5F:DB:4A:D3:28:0D:A5:36:40:6F:CE:22:88:E3:B1:0A:74:9E:C1:61:D0",
+            "a=setup:actpass",
+            "a=rtpmap:111 opus/48000/2",
+            "a=fmtp:111 minptime=10;useinbandfec=1",
+            "a=recvonly",
+            "m=application 9 UDP/DTLS/SCTP webrtc-datachannel",
+            "a=sctp-port:5000",
+            "a=candidate:2231734970 1 udp 1694498815 203.0.113.7 52011 typ srflx raddr 0.0.0.0 rport 52011",
+        ] {
+            assert!(lines.contains(&line), "missing {line}");
+        }
+        assert!(!expanded.sdp.contains("G722"));
+        assert_eq!(expanded.sdp.matches("a=candidate:").count(), 4);
+    }
+
+    #[test]
+    fn compact_is_smaller() {
+        let full = encode(&offer(), DescriptionFormat::Full).unwrap();
+        let compact = encode(&offer(), DescriptionFormat::Compact).unwrap();
+        assert!(compact.len() * 2 < full.len());
+    }
+
+    #[test]
+    fn compact_fits_a_qr_code() {
+        // bytes a version 40 code holds at error correction level L
+        const QR_CAPACITY: usize = 2953;
+        let compact = encode(&offer(), DescriptionFormat::Compact).unwrap();
+        assert!(compact.len() < QR_CAPACITY / 2);
+    }
+
+    #[test]
+    fn detects_the_full_format() {
+        // receivers send the JSON as URL safe base64
+        let json = serde_json::to_string(&offer()).unwrap();
+        let text = format!("{}\n", BASE64_URL_SAFE.encode(json));
+        let (desc, format) = decode(&text).unwrap();
+        assert_eq!(format, DescriptionFormat::Full);
+        assert_eq!(desc.sdp, OFFER);
+    }
+
+    #[test]
+    fn rejects_oversized_compact_descriptions() {
+        let mut encoder = DeflateEncoder::new(Vec::new(), Compression::best());
+        encoder
+            .write_all(&vec![b' '; MAX_DESCRIPTION_SIZE as usize + 1])
+            .unwrap();
+        let text =
+            COMPACT_PREFIX.to_string() + &BASE64_URL_SAFE_NO_PAD.encode(encoder.finish().unwrap());
+        let error = decode(&text).unwrap_err();
+        assert!(error.to_string().contains("larger than"));
+    }
+
+    #[test]
+    fn rejects_garbage() {
+        assert!(decode("not a description").is_err());
+        assert!(decode("c1.AAAA").is_err());
+    }
+}
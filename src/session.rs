@@ -0,0 +1,255 @@
+//! Progress of a sharing session, independent of the frontend driving it.
+
+use serde::Serialize;
+use webrtc::peer_connection::peer_connection_state::RTCPeerConnectionState;
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum SessionState {
+    /// no peer connection
+    #[default]
+    Idle,
+    /// peer connection created, waiting for the receiver's offer
+    AwaitingOffer,
+    /// answer handed out, waiting for ICE to start
+    Answered,
+    /// ICE is checking candidates, also while reconnecting
+    Connecting,
+    Streaming,
+    /// capture stopped or the receiver closed the connection
+    Stopped,
+    Failed {
+        reason: String,
+    },
+}
+
+#[derive(Debug, Clone)]
+pub enum SessionEvent {
+    /// a peer connection was created
+    Connect,
+    /// an offer was answered and capture started
+    Answered,
+    PeerState(RTCPeerConnectionState),
+    StreamStarted,
+    StreamStopped,
+    /// the peer connection was closed locally
+    Disconnected,
+    Error(String),
+}
+
+impl SessionState {
+    /// Applies `event`, events that don't make sense in the current state are ignored.
+    pub fn handle(&mut self, event: SessionEvent) {
+        use SessionState::*;
+        let next = match (&*self, event) {
+            (_, SessionEvent::Disconnected) => Idle,
+            (_, SessionEvent::Error(reason)) => Failed { reason },
+            (Idle | Stopped | Failed { .. }, SessionEvent::Connect) => AwaitingOffer,
+            (Idle | AwaitingOffer | Stopped | Failed { .. }, SessionEvent::Answered) => Answered,
+            (Answered | Stopped, SessionEvent::PeerState(RTCPeerConnectionState::Connecting)) => {
+                Connecting
+            }
+            (Streaming, SessionEvent::PeerState(RTCPeerConnectionState::Disconnected)) => {
+                Connecting
+            }
+            (
+                Answered | Connecting | Failed { .. },
+                SessionEvent::PeerState(RTCPeerConnectionState::Connected),
+            ) => Streaming,
+            (
+                Answered | Connecting | Streaming,
+                SessionEvent::PeerState(RTCPeerConnectionState::Failed),
+            ) => Failed {
+                reason: "ICE failed".to_string(),
+            },
+            (_, SessionEvent::PeerState(RTCPeerConnectionState::Closed)) => Stopped,
+            (Stopped, SessionEvent::StreamStarted) => Streaming,
+            (Answered | Connecting | Streaming, SessionEvent::StreamStopped) => Stopped,
+            _ => return,
+        };
+        *self = next;
+    }
+
+    /// A new peer connection can be created, replacing a finished one.
+    pub fn can_connect(&self) -> bool {
+        matches!(
+            self,
+            SessionState::Idle | SessionState::Stopped | SessionState::Failed { .. }
+        )
+    }
+    /// Offers are only taken while no other receiver is being served.
+    pub fn can_accept_offer(&self) -> bool {
+        matches!(
+            self,
+            SessionState::Idle
+                | SessionState::AwaitingOffer
+                | SessionState::Stopped
+                | SessionState::Failed { .. }
+        )
+    }
+    /// Answers to ICE restart offers.
+    pub fn can_accept_answer(&self) -> bool {
+        matches!(
+            self,
+            SessionState::Connecting | SessionState::Streaming | SessionState::Failed { .. }
+        )
+    }
+    pub fn can_stop(&self) -> bool {
+        matches!(
+            self,
+            SessionState::Answered | SessionState::Connecting | SessionState::Streaming
+        )
+    }
+    pub fn can_disconnect(&self) -> bool {
+        *self != SessionState::Idle
+    }
+}
+impl std::fmt::Display for SessionState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SessionState::Idle => f.write_str("Idle"),
+            SessionState::AwaitingOffer => f.write_str("Awaiting offer"),
+            SessionState::Answered => f.write_str("Answered"),
+            SessionState::Connecting => f.write_str("Connecting"),
+            SessionState::Streaming => f.write_str("Streaming"),
+            SessionState::Stopped => f.write_str("Stopped"),
+            SessionState::Failed { reason } => write!(f, "Failed: {reason}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn peer(state: RTCPeerConnectionState) -> SessionEvent {
+        SessionEvent::PeerState(state)
+    }
+
+    /// Applies `events` in order and returns the state after each.
+    fn run(mut state: SessionState, events: Vec<SessionEvent>) -> Vec<SessionState> {
+        events
+            .into_iter()
+            .map(|event| {
+                state.handle(event);
+                state.clone()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn happy_path() {
+        let states = run(
+            SessionState::Idle,
+            vec![
+                SessionEvent::Connect,
+                SessionEvent::Answered,
+                peer(RTCPeerConnectionState::Connecting),
+                peer(RTCPeerConnectionState::Connected),
+            ],
+        );
+        assert_eq!(
+            states,
+            [
+                SessionState::AwaitingOffer,
+                SessionState::Answered,
+                SessionState::Connecting,
+                SessionState::Streaming,
+            ]
+        );
+    }
+
+    #[test]
+    fn reconnects_after_a_disconnect() {
+        let states = run(
+            SessionState::Streaming,
+            vec![
+                peer(RTCPeerConnectionState::Disconnected),
+                peer(RTCPeerConnectionState::Connecting),
+                peer(RTCPeerConnectionState::Connected),
+            ],
+        );
+        assert_eq!(
+            states,
+            [
+                SessionState::Connecting,
+                SessionState::Connecting,
+                SessionState::Streaming,
+            ]
+        );
+    }
+
+    #[test]
+    fn stop_and_restart() {
+        let states = run(
+            SessionState::Streaming,
+            vec![SessionEvent::StreamStopped, SessionEvent::StreamStarted],
+        );
+        assert_eq!(states, [SessionState::Stopped, SessionState::Streaming]);
+    }
+
+    #[test]
+    fn failures() {
+        let mut state = SessionState::Streaming;
+        state.handle(peer(RTCPeerConnectionState::Failed));
+        assert_eq!(
+            state,
+            SessionState::Failed {
+                reason: "ICE failed".to_string()
+            }
+        );
+        // an ICE restart can still bring it back
+        state.handle(peer(RTCPeerConnectionState::Connected));
+        assert_eq!(state, SessionState::Streaming);
+
+        state.handle(SessionEvent::Error("bad offer".to_string()));
+        assert_eq!(
+            state,
+            SessionState::Failed {
+                reason: "bad offer".to_string()
+            }
+        );
+        state.handle(SessionEvent::Disconnected);
+        assert_eq!(state, SessionState::Idle);
+    }
+
+    #[test]
+    fn ignores_events_out_of_order() {
+        for (state, event) in [
+            (SessionState::Idle, SessionEvent::StreamStarted),
+            (SessionState::Idle, peer(RTCPeerConnectionState::Connected)),
+            (SessionState::AwaitingOffer, SessionEvent::Connect),
+            (SessionState::Streaming, SessionEvent::Answered),
+            (SessionState::Streaming, SessionEvent::StreamStarted),
+            (SessionState::Stopped, SessionEvent::StreamStopped),
+        ] {
+            let mut next = state.clone();
+            next.handle(event.clone());
+            assert_eq!(next, state, "{event:?}");
+        }
+    }
+
+    #[test]
+    fn gates() {
+        let failed = SessionState::Failed {
+            reason: String::new(),
+        };
+        // (state, connect, accept offer, accept answer, stop, disconnect)
+        let table = [
+            (SessionState::Idle, true, true, false, false, false),
+            (SessionState::AwaitingOffer, false, true, false, false, true),
+            (SessionState::Answered, false, false, false, true, true),
+            (SessionState::Connecting, false, false, true, true, true),
+            (SessionState::Streaming, false, false, true, true, true),
+            (SessionState::Stopped, true, true, false, false, true),
+            (failed, true, true, true, false, true),
+        ];
+        for (state, connect, offer, answer, stop, disconnect) in table {
+            assert_eq!(state.can_connect(), connect, "{state}");
+            assert_eq!(state.can_accept_offer(), offer, "{state}");
+            assert_eq!(state.can_accept_answer(), answer, "{state}");
+            assert_eq!(state.can_stop(), stop, "{state}");
+            assert_eq!(state.can_disconnect(), disconnect, "{state}");
+        }
+    }
+}
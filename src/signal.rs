@@ -0,0 +1,207 @@
+use std::f32::consts::TAU;
+use std::fmt::Display;
+
+use serde::{Deserialize, Serialize};
+
+/// Sample rate of the generated signals, matches the opus rate so no resampling happens.
+pub const SIGNAL_SAMPLE_RATE: u32 = 48_000;
+pub const SIGNAL_CHANNELS: u16 = 2;
+
+/// Synthetic source for checking the pipeline without a capture device.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum TestSignal {
+    Sine {
+        frequency: f32,
+    },
+    /// logarithmic sweep from `from` to `to` Hz, restarting every `seconds`
+    Sweep {
+        from: f32,
+        to: f32,
+        seconds: f32,
+    },
+    PinkNoise,
+    /// a one second tone on each channel in turn
+    ChannelId,
+    /// short click every `interval_ms`, for measuring latency by ear or with a scope
+    Click {
+        interval_ms: u32,
+    },
+}
+impl TestSignal {
+    /// Rejects parameters that would generate NaN, like a sweep starting at 0 Hz.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if let TestSignal::Sweep { from, to, seconds } = *self {
+            if from.is_nan() || to.is_nan() || from <= 0. || to <= 0. {
+                anyhow::bail!("sweep frequencies have to be above 0 Hz, got {from} - {to}");
+            }
+            if seconds.is_nan() || seconds <= 0. {
+                anyhow::bail!("sweep duration has to be above 0 s, got {seconds}");
+            }
+        }
+        Ok(())
+    }
+    /// Signals listed when the config doesn't name any.
+    pub fn defaults() -> Vec<TestSignal> {
+        vec![
+            TestSignal::Sine { frequency: 440. },
+            TestSignal::Sweep {
+                from: 20.,
+                to: 20_000.,
+                seconds: 10.,
+            },
+            TestSignal::PinkNoise,
+            TestSignal::ChannelId,
+            TestSignal::Click { interval_ms: 1000 },
+        ]
+    }
+}
+impl Display for TestSignal {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TestSignal::Sine { frequency } => write!(f, "Sine {frequency} Hz"),
+            TestSignal::Sweep { from, to, seconds } => {
+                write!(f, "Sweep {from}-{to} Hz / {seconds} s")
+            }
+            TestSignal::PinkNoise => write!(f, "Pink noise"),
+            TestSignal::ChannelId => write!(f, "Channel identification"),
+            TestSignal::Click { interval_ms } => write!(f, "Click every {interval_ms} ms"),
+        }
+    }
+}
+
+/// Produces interleaved [`SIGNAL_CHANNELS`] channel samples at [`SIGNAL_SAMPLE_RATE`].
+pub struct SignalGenerator {
+    signal: TestSignal,
+    /// frames generated so far
+    position: u64,
+    phase: f32,
+    rng: u32,
+    /// Paul Kellet's pink noise filter state
+    pink: [f32; 7],
+}
+impl SignalGenerator {
+    pub fn new(signal: TestSignal) -> Self {
+        Self {
+            signal,
+            position: 0,
+            phase: 0.,
+            rng: 0x1234_5678,
+            pink: [0.; 7],
+        }
+    }
+    pub fn fill(&mut self, out: &mut [f32]) {
+        let rate = SIGNAL_SAMPLE_RATE as f32;
+        for frame in out.chunks_exact_mut(SIGNAL_CHANNELS as usize) {
+            // `None` puts the sample on every channel
+            let (sample, channel) = match self.signal {
+                TestSignal::Sine { frequency } => (self.tone(frequency), None),
+                TestSignal::Sweep { from, to, seconds } => {
+                    let progress = self.sweep_progress(seconds);
+                    (self.tone(from * (to / from).powf(progress)), None)
+                }
+                TestSignal::PinkNoise => (self.pink_noise(), None),
+                TestSignal::ChannelId => {
+                    let channel = self.position / SIGNAL_SAMPLE_RATE as u64;
+                    let channel = (channel % SIGNAL_CHANNELS as u64) as usize;
+                    // left and right at different pitches so they can't be mixed up
+                    (self.tone(440. * (channel + 1) as f32), Some(channel))
+                }
+                TestSignal::Click { interval_ms } => {
+                    let interval = (SIGNAL_SAMPLE_RATE as u64 * interval_ms as u64 / 1000).max(1);
+                    let offset = (self.position % interval) as f32;
+                    let len = rate / 1000.; // 1 ms
+                    (0.9 * (1. - offset / len).max(0.), None)
+                }
+            };
+            for (i, s) in frame.iter_mut().enumerate() {
+                *s = if channel.is_none_or(|c| c == i) {
+                    sample
+                } else {
+                    0.
+                };
+            }
+            self.position += 1;
+        }
+    }
+    /// How far into the current sweep period the generator is, 0.0 - 1.0.
+    fn sweep_progress(&self, seconds: f32) -> f32 {
+        // an f32 frame count stops resolving single frames after 2^24 frames (~6 minutes)
+        let t = self.position as f64 / SIGNAL_SAMPLE_RATE as f64;
+        ((t % seconds as f64) / seconds as f64) as f32
+    }
+    fn tone(&mut self, frequency: f32) -> f32 {
+        self.phase = (self.phase + TAU * frequency / SIGNAL_SAMPLE_RATE as f32) % TAU;
+        0.5 * self.phase.sin()
+    }
+    fn pink_noise(&mut self) -> f32 {
+        // xorshift32
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 17;
+        self.rng ^= self.rng << 5;
+        let white = self.rng as f32 / u32::MAX as f32 * 2. - 1.;
+
+        let b = &mut self.pink;
+        b[0] = 0.99886 * b[0] + white * 0.0555179;
+        b[1] = 0.99332 * b[1] + white * 0.0750759;
+        b[2] = 0.96900 * b[2] + white * 0.153852;
+        b[3] = 0.86650 * b[3] + white * 0.3104856;
+        b[4] = 0.55000 * b[4] + white * 0.5329522;
+        b[5] = -0.7616 * b[5] - white * 0.0168980;
+        let pink = b[0] + b[1] + b[2] + b[3] + b[4] + b[5] + b[6] + white * 0.5362;
+        b[6] = white * 0.115926;
+        // the filter peaks around ±10, keep the result well below full scale
+        pink * 0.05
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sweep(from: f32, to: f32, seconds: f32) -> TestSignal {
+        TestSignal::Sweep { from, to, seconds }
+    }
+
+    #[test]
+    fn rejects_sweeps_that_would_be_nan() {
+        for signal in [
+            sweep(0., 20_000., 10.),
+            sweep(-20., 20_000., 10.),
+            sweep(20., 0., 10.),
+            sweep(f32::NAN, 20_000., 10.),
+            sweep(20., 20_000., 0.),
+        ] {
+            assert!(signal.validate().is_err(), "{signal}");
+        }
+        for signal in TestSignal::defaults() {
+            signal.validate().unwrap();
+        }
+    }
+
+    #[test]
+    fn sweep_stays_precise_after_hours() {
+        let mut generator = SignalGenerator::new(sweep(20., 20_000., 10.));
+        let period = SIGNAL_SAMPLE_RATE as u64 * 10;
+        generator.position = 12_345;
+        let start = generator.sweep_progress(10.);
+        // ten hours in
+        generator.position = 3_600 * period + 12_345;
+        assert!((generator.sweep_progress(10.) - start).abs() < 1e-6);
+        generator.position = 3_600 * period + 12_346;
+        assert!(generator.sweep_progress(10.) > start);
+    }
+
+    #[test]
+    fn defaults_generate_finite_samples() {
+        for signal in TestSignal::defaults() {
+            let mut generator = SignalGenerator::new(signal);
+            let mut out = vec![0.; SIGNAL_SAMPLE_RATE as usize * SIGNAL_CHANNELS as usize];
+            generator.fill(&mut out);
+            assert!(
+                out.iter().all(|s| s.is_finite() && s.abs() <= 1.),
+                "{signal}"
+            );
+        }
+    }
+}
@@ -0,0 +1,366 @@
+use std::io::{Read, Seek};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use cpal::traits::{DeviceTrait, StreamTrait};
+use cpal::{SampleFormat, SampleRate, SupportedBufferSize, SupportedStreamConfig};
+use serde::{Deserialize, Serialize};
+use tokio::sync::{broadcast, mpsc};
+use tokio::task::JoinHandle;
+
+use crate::encoder::to_stereo;
+use crate::signal::{SIGNAL_CHANNELS, SIGNAL_SAMPLE_RATE, SignalGenerator, TestSignal};
+
+/// Audio file shared as if it was a capture device.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FileSource {
+    pub path: PathBuf,
+    #[serde(default)]
+    pub looping: bool,
+}
+
+/// Anything that can feed samples into [`crate::connection::Connection::start`].
+pub enum Source {
+    Device(cpal::Device),
+    File(FileSource),
+    Signal(TestSignal),
+}
+impl Source {
+    pub fn name(&self) -> Option<String> {
+        match self {
+            Source::Device(device) => device.name().ok(),
+            Source::File(file) => {
+                let name = file.path.file_name()?.to_string_lossy();
+                Some(if file.looping {
+                    format!("File: {name} (loop)")
+                } else {
+                    format!("File: {name}")
+                })
+            }
+            Source::Signal(signal) => Some(format!("Signal: {signal}")),
+        }
+    }
+    /// Starts producing samples, they stop once the returned [`SourceStream`] is dropped.
+    /// Capture devices report errors while running to `errors`.
+    pub fn start(
+        &self,
+        errors: &mpsc::UnboundedSender<cpal::StreamError>,
+    ) -> anyhow::Result<(
+        SourceStream,
+        broadcast::Receiver<Vec<f32>>,
+        SupportedStreamConfig,
+    )> {
+        match self {
+            Source::Device(device) => {
+                let (stream, receiver, _sender) = crate::create_stream(device, errors.clone())?;
+                stream.play()?;
+                let config = device.default_output_config()?;
+                // let config =
+                //     device
+                //         .supported_output_configs()
+                //         .unwrap()
+                //         .into_iter()
+                //         .find(|v| {
+                //             v.try_with_sample_rate(SampleRate(48000)).is_some()
+                //                 && v.channels() == 2
+                //                 && v.sample_format() == SampleFormat::F32
+                //         });
+                // let config = config.unwrap().with_sample_rate(SampleRate(48000));
+                Ok((SourceStream::Cpal(stream), receiver, config))
+            }
+            Source::File(file) => {
+                let paced = create_file_stream(&file.path, file.looping)?;
+                Ok(paced.into_parts())
+            }
+            Source::Signal(signal) => Ok(create_signal_stream(*signal).into_parts()),
+        }
+    }
+}
+
+/// Keeps a started [`Source`] running.
+pub enum SourceStream {
+    Cpal(cpal::Stream),
+    Task {
+        task: JoinHandle<()>,
+        ended: Arc<AtomicBool>,
+    },
+}
+impl SourceStream {
+    /// Whether the source ran out of samples by itself, only files without looping do.
+    /// The channel it fed is closed then, like when it is dropped.
+    pub fn ended(&self) -> bool {
+        match self {
+            SourceStream::Cpal(_) => false,
+            SourceStream::Task { ended, .. } => ended.load(Ordering::Acquire),
+        }
+    }
+}
+impl Drop for SourceStream {
+    fn drop(&mut self) {
+        if let SourceStream::Task { task, .. } = self {
+            task.abort();
+        }
+    }
+}
+
+/// Blocks of this many milliseconds are sent per tick, similar to a capture callback.
+const FILE_BLOCK_MS: u32 = 10;
+
+/// Samples generated in real time by a task, see [`create_file_stream`].
+pub struct PacedStream {
+    /// sends a block every [`FILE_BLOCK_MS`] until aborted or the source ends
+    pub task: JoinHandle<()>,
+    pub receiver: broadcast::Receiver<Vec<f32>>,
+    pub config: SupportedStreamConfig,
+    /// set before the sender is dropped at the end of the source
+    pub ended: Arc<AtomicBool>,
+}
+impl PacedStream {
+    fn into_parts(
+        self,
+    ) -> (
+        SourceStream,
+        broadcast::Receiver<Vec<f32>>,
+        SupportedStreamConfig,
+    ) {
+        let stream = SourceStream::Task {
+            task: self.task,
+            ended: self.ended,
+        };
+        (stream, self.receiver, self.config)
+    }
+}
+
+/// Plays a wav file into a broadcast channel in real time, like [`crate::create_stream`]
+/// does for a capture device. The file is read as it plays and mapped to stereo, see
+/// [`to_stereo`]. Without `looping` the sender is dropped at the end of the file.
+pub fn create_file_stream(path: &Path, looping: bool) -> anyhow::Result<PacedStream> {
+    let reader = hound::WavReader::open(path)?;
+    let sample_rate = reader.spec().sample_rate;
+    let mut blocks =
+        WavBlocks::new(reader, looping).map_err(|e| anyhow::anyhow!("{}: {e}", path.display()))?;
+    let path = path.to_path_buf();
+    Ok(spawn_paced(2, sample_rate, move |block| {
+        match blocks.next_block(block / 2) {
+            Ok(data) => data,
+            Err(e) => {
+                log::warn!("reading {} failed: {e}", path.display());
+                None
+            }
+        }
+    }))
+}
+
+/// Reads a wav file block by block as stereo f32 samples.
+struct WavBlocks<R> {
+    reader: hound::WavReader<R>,
+    channels: usize,
+    /// maps integer samples to -1.0 - 1.0, `None` for float files
+    scale: Option<f32>,
+    looping: bool,
+    /// samples as read, before [`to_stereo`]
+    samples: Vec<f32>,
+}
+impl<R: Read + Seek> WavBlocks<R> {
+    fn new(reader: hound::WavReader<R>, looping: bool) -> anyhow::Result<Self> {
+        let spec = reader.spec();
+        if reader.duration() == 0 {
+            anyhow::bail!("no samples");
+        }
+        Ok(Self {
+            channels: spec.channels as usize,
+            scale: match spec.sample_format {
+                hound::SampleFormat::Float => None,
+                hound::SampleFormat::Int => Some(1. / (1u64 << (spec.bits_per_sample - 1)) as f32),
+            },
+            reader,
+            looping,
+            samples: Vec::new(),
+        })
+    }
+    /// The next `frames` frames, fewer at the end of the file and `None` after it. Looping
+    /// files start over within the block.
+    fn next_block(&mut self, frames: usize) -> anyhow::Result<Option<Vec<f32>>> {
+        let wanted = frames * self.channels;
+        self.samples.clear();
+        while self.samples.len() < wanted {
+            let count = wanted - self.samples.len();
+            let read = match self.scale {
+                None => self.read(count, |s: f32| s)?,
+                Some(scale) => self.read(count, |s: i32| s as f32 * scale)?,
+            };
+            if read == 0 {
+                if !self.looping {
+                    break;
+                }
+                self.reader.seek(0)?;
+            }
+        }
+        if self.samples.is_empty() {
+            return Ok(None);
+        }
+        let mut data = Vec::with_capacity(frames * 2);
+        to_stereo(&self.samples, self.channels, &mut data);
+        Ok(Some(data))
+    }
+    /// Appends up to `count` samples, returns how many were read.
+    fn read<S: hound::Sample>(
+        &mut self,
+        count: usize,
+        convert: impl Fn(S) -> f32,
+    ) -> hound::Result<usize> {
+        let before = self.samples.len();
+        for sample in self.reader.samples::<S>().take(count) {
+            self.samples.push(convert(sample?));
+        }
+        Ok(self.samples.len() - before)
+    }
+}
+
+/// Generates a [`TestSignal`] into a broadcast channel in real time.
+pub fn create_signal_stream(signal: TestSignal) -> PacedStream {
+    let mut generator = SignalGenerator::new(signal);
+    spawn_paced(SIGNAL_CHANNELS, SIGNAL_SAMPLE_RATE, move |block| {
+        let mut data = vec![0.; block];
+        generator.fill(&mut data);
+        Some(data)
+    })
+}
+
+/// Sends a block of samples from `next_block` every [`FILE_BLOCK_MS`], the argument is the
+/// block length in samples. Returning `None` ends the stream.
+fn spawn_paced(
+    channels: u16,
+    sample_rate: u32,
+    mut next_block: impl FnMut(usize) -> Option<Vec<f32>> + Send + 'static,
+) -> PacedStream {
+    let config = SupportedStreamConfig::new(
+        channels,
+        SampleRate(sample_rate),
+        SupportedBufferSize::Unknown,
+        SampleFormat::F32,
+    );
+    let (send, receiver) = broadcast::channel(30);
+    let ended = Arc::new(AtomicBool::new(false));
+
+    let task = tokio::spawn({
+        let ended = ended.clone();
+        async move {
+            let mut interval =
+                tokio::time::interval(std::time::Duration::from_millis(FILE_BLOCK_MS as u64));
+            let mut remainder = 0;
+            loop {
+                interval.tick().await;
+                let frames = block_frames(sample_rate, &mut remainder);
+                let Some(data) = next_block(frames * channels as usize) else {
+                    ended.store(true, Ordering::Release);
+                    break;
+                };
+                // nobody listening isn't an error, the data is just dropped
+                let _ = send.send(data);
+            }
+        }
+    });
+    PacedStream {
+        task,
+        receiver,
+        config,
+        ended,
+    }
+}
+
+/// Frames in the next block. Rates like 22050 Hz don't divide into whole blocks, the
+/// fraction left over is carried in `remainder` (in thousandths of a frame) so no time is lost.
+fn block_frames(sample_rate: u32, remainder: &mut u32) -> usize {
+    let thousandths = sample_rate * FILE_BLOCK_MS + *remainder;
+    *remainder = thousandths % 1000;
+    (thousandths / 1000) as usize
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    /// A 16 bit wav file in memory holding `samples`.
+    fn wav(channels: u16, samples: &[i16]) -> hound::WavReader<Cursor<Vec<u8>>> {
+        let spec = hound::WavSpec {
+            channels,
+            sample_rate: 48_000,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let mut data = Cursor::new(Vec::new());
+        let mut writer = hound::WavWriter::new(&mut data, spec).unwrap();
+        for sample in samples {
+            writer.write_sample(*sample).unwrap();
+        }
+        writer.finalize().unwrap();
+        data.set_position(0);
+        hound::WavReader::new(data).unwrap()
+    }
+
+    #[test]
+    fn mono_files_play_on_both_sides() {
+        let mut blocks = WavBlocks::new(wav(1, &[16384, -16384, 0]), false).unwrap();
+        assert_eq!(
+            blocks.next_block(2).unwrap(),
+            Some(vec![0.5, 0.5, -0.5, -0.5])
+        );
+        // the rest of the file, then the end
+        assert_eq!(blocks.next_block(2).unwrap(), Some(vec![0., 0.]));
+        assert_eq!(blocks.next_block(2).unwrap(), None);
+    }
+
+    #[test]
+    fn looping_files_start_over_within_a_block() {
+        let mut blocks = WavBlocks::new(wav(2, &[1, 2, 3, 4]), true).unwrap();
+        let scale = 1. / 32768.;
+        let block = blocks.next_block(3).unwrap().unwrap();
+        let expected = [1, 2, 3, 4, 1, 2].map(|s| s as f32 * scale);
+        assert_eq!(block, expected);
+        assert_eq!(
+            blocks.next_block(1).unwrap().unwrap(),
+            [3., 4.].map(|s| s * scale)
+        );
+    }
+
+    #[test]
+    fn surround_files_are_mixed_to_stereo() {
+        // front left only, then center only
+        let frames = [[16384, 0, 0, 0, 0, 0], [0, 0, 16384, 0, 0, 0]];
+        let mut blocks = WavBlocks::new(wav(6, frames.as_flattened()), false).unwrap();
+        let block = blocks.next_block(2).unwrap().unwrap();
+        assert_eq!(block.len(), 4);
+        assert!(block[0] > 0. && block[1] == 0., "{block:?}");
+        assert!(block[2] > 0. && block[2] == block[3], "{block:?}");
+    }
+
+    #[test]
+    fn empty_files_are_rejected() {
+        assert!(WavBlocks::new(wav(2, &[]), false).is_err());
+    }
+
+    #[test]
+    fn blocks_add_up_to_the_sample_rate() {
+        for sample_rate in [8_000, 11_025, 22_050, 44_100, 48_000, 88_200] {
+            let mut remainder = 0;
+            let frames = (0..1000 / FILE_BLOCK_MS)
+                .map(|_| block_frames(sample_rate, &mut remainder))
+                .sum::<usize>();
+            assert_eq!(frames, sample_rate as usize, "{sample_rate} Hz");
+            assert_eq!(remainder, 0);
+        }
+    }
+
+    #[test]
+    fn blocks_differ_by_at_most_one_frame() {
+        let mut remainder = 0;
+        let blocks = (0..10)
+            .map(|_| block_frames(22_050, &mut remainder))
+            .collect::<Vec<_>>();
+        assert!(blocks.iter().all(|b| *b == 220 || *b == 221), "{blocks:?}");
+    }
+}
@@ -0,0 +1,187 @@
+use std::time::Instant;
+
+use webrtc::peer_connection::RTCPeerConnection;
+use webrtc::rtcp::receiver_report::ReceiverReport;
+use webrtc::stats::{ICECandidateStats, StatsReportType};
+
+/// Latest RTCP receiver report block for the audio track.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ReceiverReportStats {
+    /// interarrival jitter in RTP timestamp units
+    pub jitter: u32,
+    pub fraction_lost: u8,
+    pub total_lost: u32,
+}
+impl ReceiverReportStats {
+    /// Picks the report block about `ssrc`, our audio track, out of an RTCP packet if it is a
+    /// receiver report.
+    pub fn from_packet(
+        packet: &(dyn webrtc::rtcp::packet::Packet + Send + Sync),
+        ssrc: u32,
+    ) -> Option<Self> {
+        let report = packet.as_any().downcast_ref::<ReceiverReport>()?;
+        let block = report.reports.iter().find(|block| block.ssrc == ssrc)?;
+        Some(Self {
+            jitter: block.jitter,
+            fraction_lost: block.fraction_lost,
+            total_lost: block.total_lost,
+        })
+    }
+    pub fn jitter_ms(&self) -> f64 {
+        // the audio clock runs at 48 kHz
+        self.jitter as f64 / 48.
+    }
+    /// 0.0 - 1.0, the report has it in 1/256
+    pub fn fraction_lost(&self) -> f64 {
+        self.fraction_lost as f64 / 256.
+    }
+}
+
+/// Snapshot of the outgoing stream, sent as [`crate::connection::ConnectionEvent::Stats`].
+#[derive(Debug, Clone)]
+pub struct ConnectionStats {
+    pub sampled_at: Instant,
+    pub bytes_sent: u64,
+    pub packets_sent: u64,
+    /// bits per second since the previous snapshot
+    pub bitrate: Option<f64>,
+    pub packets_lost: Option<i64>,
+    /// 0.0 - 1.0
+    pub fraction_lost: Option<f64>,
+    pub jitter_ms: Option<f64>,
+    pub rtt_ms: Option<f64>,
+    /// `local -> remote` of the nominated candidate pair
+    pub candidate_pair: Option<String>,
+    pub transport: Option<String>,
+}
+impl ConnectionStats {
+    pub async fn collect(
+        peer_connection: &RTCPeerConnection,
+        receiver_report: Option<ReceiverReportStats>,
+        previous: Option<&ConnectionStats>,
+    ) -> Self {
+        let report = peer_connection.get_stats().await;
+        let mut stats = Self {
+            sampled_at: Instant::now(),
+            bytes_sent: 0,
+            packets_sent: 0,
+            bitrate: None,
+            packets_lost: None,
+            fraction_lost: None,
+            jitter_ms: receiver_report.map(|r| r.jitter_ms()),
+            rtt_ms: None,
+            candidate_pair: None,
+            transport: None,
+        };
+
+        let candidate = |id: &str| {
+            report.reports.values().find_map(|r| match r {
+                StatsReportType::LocalCandidate(c) | StatsReportType::RemoteCandidate(c)
+                    if c.id == id =>
+                {
+                    Some(c)
+                }
+                _ => None,
+            })
+        };
+        let describe =
+            |c: &ICECandidateStats| format!("{}:{} ({})", c.ip, c.port, c.candidate_type);
+
+        for r in report.reports.values() {
+            match r {
+                StatsReportType::OutboundRTP(rtp) if rtp.kind == "audio" => {
+                    stats.bytes_sent += rtp.bytes_sent;
+                    stats.packets_sent += rtp.packets_sent;
+                }
+                StatsReportType::RemoteInboundRTP(rtp) if rtp.kind == "audio" => {
+                    stats.packets_lost = Some(rtp.packets_lost);
+                    stats.fraction_lost = Some(rtp.fraction_lost);
+                    stats.rtt_ms = rtp.round_trip_time.map(|t| t * 1000.).or(stats.rtt_ms);
+                }
+                StatsReportType::CandidatePair(pair) if pair.nominated => {
+                    let local = candidate(&pair.local_candidate_id);
+                    let remote = candidate(&pair.remote_candidate_id);
+                    if let (Some(local), Some(remote)) = (local, remote) {
+                        stats.candidate_pair =
+                            Some(format!("{} -> {}", describe(local), describe(remote)));
+                        stats.transport = Some(local.network_type.to_string());
+                    }
+                    if stats.rtt_ms.is_none() && pair.current_round_trip_time > 0. {
+                        stats.rtt_ms = Some(pair.current_round_trip_time * 1000.);
+                    }
+                }
+                _ => {}
+            }
+        }
+        // the receiver report is fresher than what the interceptors aggregate
+        if let Some(rr) = receiver_report {
+            stats.fraction_lost = Some(rr.fraction_lost());
+            stats.packets_lost.get_or_insert(rr.total_lost as i64);
+        }
+
+        if let Some(previous) = previous {
+            let elapsed = stats.sampled_at - previous.sampled_at;
+            let sent = stats.bytes_sent.saturating_sub(previous.bytes_sent);
+            if !elapsed.is_zero() {
+                stats.bitrate = Some(sent as f64 * 8. / elapsed.as_secs_f64());
+            }
+        }
+        stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use webrtc::rtcp::reception_report::ReceptionReport;
+    use webrtc::rtcp::sender_report::SenderReport;
+
+    use super::*;
+
+    fn block(ssrc: u32, fraction_lost: u8) -> ReceptionReport {
+        ReceptionReport {
+            ssrc,
+            fraction_lost,
+            total_lost: 7,
+            jitter: 96,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn picks_the_block_about_our_track() {
+        let report = ReceiverReport {
+            ssrc: 1,
+            reports: vec![block(1234, 64), block(5678, 128)],
+            ..Default::default()
+        };
+        let stats = ReceiverReportStats::from_packet(&report, 1234).unwrap();
+        assert_eq!(stats.fraction_lost, 64);
+        assert_eq!(stats.total_lost, 7);
+        assert_eq!(stats.jitter, 96);
+        let stats = ReceiverReportStats::from_packet(&report, 5678).unwrap();
+        assert_eq!(stats.fraction_lost, 128);
+        assert!(ReceiverReportStats::from_packet(&report, 42).is_none());
+    }
+
+    #[test]
+    fn ignores_other_packets() {
+        let report = SenderReport {
+            ssrc: 1234,
+            reports: vec![block(1234, 64)],
+            ..Default::default()
+        };
+        assert!(ReceiverReportStats::from_packet(&report, 1234).is_none());
+    }
+
+    #[test]
+    fn converts_loss_and_jitter() {
+        let stats = ReceiverReportStats {
+            jitter: 96,
+            fraction_lost: 64,
+            total_lost: 0,
+        };
+        assert_eq!(stats.jitter_ms(), 2.);
+        assert_eq!(stats.fraction_lost(), 0.25);
+        assert_eq!(ReceiverReportStats::default().fraction_lost(), 0.);
+    }
+}
@@ -0,0 +1,194 @@
+//! Terminal frontend, maps keys to [`Command`]s and draws with [`crate::ui`].
+
+use crossterm::event::{Event, EventStream, KeyEvent, KeyEventKind};
+use futures::{FutureExt, StreamExt};
+use qrcodegen::{QrCode, QrCodeEcc};
+use ratatui::{DefaultTerminal, widgets::ListState};
+use tokio::select;
+
+use crate::app::{App, Command, Frontend};
+use crate::keymap::{Action, Context, Keymap};
+use crate::logging::LogBuffer;
+use crate::ui::draw;
+
+/// Records moved per PageUp/PageDown in the log pane.
+const LOG_SCROLL_STEP: usize = 5;
+/// Redraws without input, for the log pane and the latency readout.
+const REDRAW_INTERVAL: tokio::time::Duration = tokio::time::Duration::from_millis(100);
+
+#[derive(Debug, Default, PartialEq)]
+pub enum Selected {
+    #[default]
+    None,
+    Left,
+    Right,
+}
+
+/// What the terminal shows besides the app's own state.
+#[derive(Default)]
+pub struct View {
+    pub focus: Selected,
+    pub list_state: ListState,
+    /// render the local description as a QR code instead of text
+    pub show_qr: bool,
+    pub logs: LogBuffer,
+    pub show_log: bool,
+    /// records scrolled up from the newest one
+    pub log_scroll: usize,
+    pub keymap: Keymap,
+    pub show_help: bool,
+    /// the description last shown as a QR code and its code, `None` when it didn't fit
+    qr: Option<(String, Option<QrCode>)>,
+}
+impl View {
+    /// Where keys currently apply, in order of precedence.
+    pub fn contexts(&self) -> Vec<Context> {
+        match self.focus {
+            Selected::None => vec![Context::Global],
+            Selected::Left => vec![Context::Devices, Context::Global],
+            Selected::Right => vec![Context::Session, Context::Global],
+        }
+    }
+    /// `description` as a QR code, only encoded again when it changed.
+    pub fn qr_code(&mut self, description: &str) -> Option<&QrCode> {
+        if self
+            .qr
+            .as_ref()
+            .is_none_or(|(encoded, _)| encoded != description)
+        {
+            let qr = QrCode::encode_text(description, QrCodeEcc::Low).ok();
+            self.qr = Some((description.to_string(), qr));
+        }
+        self.qr.as_ref().and_then(|(_, qr)| qr.as_ref())
+    }
+}
+
+pub struct Tui {
+    terminal: DefaultTerminal,
+    events: EventStream,
+    redraw: tokio::time::Interval,
+    view: View,
+    /// whether the last render showed the error popup, keys go to it then
+    error_shown: bool,
+    /// device the list was last synced to, the receiver can switch it too
+    selected_device: Option<usize>,
+}
+impl Tui {
+    pub fn new(terminal: DefaultTerminal, logs: LogBuffer, keymap: Keymap) -> Self {
+        Self {
+            terminal,
+            events: EventStream::new(),
+            redraw: tokio::time::interval(REDRAW_INTERVAL),
+            view: View {
+                logs,
+                keymap,
+                ..Default::default()
+            },
+            error_shown: false,
+            selected_device: None,
+        }
+    }
+    fn handle_crossterm_event(&mut self, event: Event) -> Option<Command> {
+        match event {
+            // it's important to check that the event is a key press event as
+            // crossterm also emits key release and repeat events on Windows.
+            Event::Key(key_event) if key_event.kind == KeyEventKind::Press => {
+                self.handle_key_event(key_event)
+            }
+            Event::Paste(description) => Some(Command::Description { description }),
+            _ => None,
+        }
+    }
+    fn handle_key_event(&mut self, key_event: KeyEvent) -> Option<Command> {
+        let view = &mut self.view;
+        let code = key_event.code;
+        if self.error_shown {
+            return (view.keymap.action(code, &[Context::Popup]) == Some(Action::Dismiss))
+                .then_some(Command::DismissError);
+        }
+        if view.show_help {
+            let contexts = [Context::Popup, Context::Global];
+            if matches!(
+                view.keymap.action(code, &contexts),
+                Some(Action::Dismiss | Action::Help)
+            ) {
+                view.show_help = false;
+            }
+            return None;
+        }
+        match view.keymap.action(code, &view.contexts())? {
+            Action::Quit => Some(Command::Quit),
+            Action::Help => {
+                view.show_help = true;
+                None
+            }
+            Action::FocusDevices => {
+                view.focus = Selected::Left;
+                None
+            }
+            Action::FocusSession => {
+                view.focus = Selected::Right;
+                None
+            }
+            Action::ToggleLog => {
+                view.show_log = !view.show_log;
+                view.log_scroll = 0;
+                None
+            }
+            Action::ScrollLogUp if view.show_log => {
+                view.log_scroll = (view.log_scroll + LOG_SCROLL_STEP)
+                    .min(view.logs.records().len().saturating_sub(1));
+                None
+            }
+            Action::ScrollLogDown if view.show_log => {
+                view.log_scroll = view.log_scroll.saturating_sub(LOG_SCROLL_STEP);
+                None
+            }
+            Action::MoveUp => {
+                view.list_state.select_previous();
+                None
+            }
+            Action::MoveDown => {
+                view.list_state.select_next();
+                None
+            }
+            Action::SelectDevice => view
+                .list_state
+                .selected()
+                .map(|device| Command::SelectDevice { device }),
+            Action::Connect => Some(Command::Connect),
+            Action::AnswerOfferFile => Some(Command::AnswerOfferFile),
+            Action::PasteOffer => Some(Command::PasteFromClipboard),
+            Action::CopyDescription => Some(Command::CopyDescription),
+            Action::ToggleQr => {
+                view.show_qr = !view.show_qr;
+                None
+            }
+            Action::LatencyClick => Some(Command::LatencyClick),
+            Action::Stop => Some(Command::Stop),
+            Action::Disconnect => Some(Command::Disconnect),
+            Action::ScrollLogUp | Action::ScrollLogDown | Action::Dismiss => None,
+        }
+    }
+}
+impl Frontend for Tui {
+    fn render(&mut self, app: &App) -> anyhow::Result<()> {
+        self.error_shown = app.error().is_some();
+        if self.selected_device != Some(app.selected_device) {
+            self.selected_device = Some(app.selected_device);
+            self.view.list_state.select(Some(app.selected_device));
+        }
+        self.terminal
+            .draw(|frame| draw(app, &mut self.view, frame))?;
+        Ok(())
+    }
+    async fn next_command(&mut self) -> Option<Command> {
+        select! {
+            event = self.events.next().fuse() => match event {
+                Some(Ok(event)) => self.handle_crossterm_event(event),
+                _ => None,
+            },
+            _ = self.redraw.tick() => None,
+        }
+    }
+}
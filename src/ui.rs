@@ -1,94 +1,284 @@
 use std::rc::Rc;
 
 use cpal::traits::DeviceTrait;
-use crossterm::event::KeyCode;
+use log::Level;
+use qrcodegen::QrCode;
 use ratatui::{
     Frame,
     layout::{Constraint, Flex, Layout, Rect},
-    style::{Style, Stylize},
+    style::Stylize,
     symbols::border,
     text::{Line, ToSpan},
-    widgets::{Block, Borders, Clear, List, ListItem, Padding, Paragraph, StatefulWidget, Widget},
+    widgets::{
+        Block, Borders, Clear, List, ListItem, Padding, Paragraph, StatefulWidget, Widget, Wrap,
+    },
 };
-use webrtc::peer_connection::peer_connection_state::RTCPeerConnectionState;
 
-use crate::app::{App, Selected};
+use crate::app::App;
+use crate::keymap::{Action, Key, Keymap};
+use crate::latency::LatencyBreakdown;
+use crate::reconnect::MAX_ATTEMPTS;
+use crate::session::SessionState;
+use crate::stats::ConnectionStats;
+use crate::tui::{Selected, View};
 
-pub fn draw_right_panel(app: &mut App, frame: &mut Frame, layout: &Rc<[Rect]>) {
+/// Panels side by side, the log pane below them when shown, the error popup on top.
+pub fn draw(app: &App, view: &mut View, frame: &mut Frame) {
+    let mut area = frame.area();
+    if view.show_log {
+        let [panels, log] =
+            Layout::vertical([Constraint::Percentage(70), Constraint::Percentage(30)]).areas(area);
+        draw_log_panel(view, frame, log);
+        area = panels;
+    }
+    let layout = Layout::default()
+        .direction(ratatui::layout::Direction::Horizontal)
+        .constraints(vec![Constraint::Percentage(50), Constraint::Percentage(50)])
+        .split(area);
+    draw_left_panel(app, view, frame, &layout);
+    draw_right_panel(app, view, frame, &layout);
+    if app.error().is_some() {
+        draw_popup(app, view, frame);
+    } else if view.show_help {
+        draw_help(view, frame);
+    }
+}
+
+pub fn draw_right_panel(app: &App, view: &mut View, frame: &mut Frame, layout: &Rc<[Rect]>) {
     let layout = Layout::default()
         .direction(ratatui::layout::Direction::Vertical)
         .constraints(vec![Constraint::Percentage(50), Constraint::Percentage(50)])
         .split(layout[1]);
-    let title = Line::from(" Counter App Tutorial ".bold());
-    let instructions = Line::from(vec![
-        " Decrement ".into(),
-        "<Left>".blue().bold(),
-        " Increment ".into(),
-        "<Right>".blue().bold(),
-        " Quit ".into(),
-        "<Q> ".blue().bold(),
-    ]);
+    let title = Line::from(" Description ".bold());
+    let keys = hints(
+        &view.keymap,
+        &[
+            Action::Connect,
+            Action::PasteOffer,
+            Action::CopyDescription,
+            Action::Help,
+        ],
+    );
     let mut block = Block::bordered()
         .title(title.centered())
         .border_set(border::PLAIN);
-    if *app.state() == Selected::Right {
+    if view.focus == Selected::Right {
         block = block
             .border_set(border::THICK)
-            .title_bottom(instructions.centered());
+            .title_bottom(instructions(&keys).centered());
     }
 
-    Paragraph::new(Line::from(format!("desc: {}", app.local_desc)))
-        .centered()
-        .block(block)
-        .render(layout[1], frame.buffer_mut());
-
-    let mut block = Block::bordered().border_set(border::PLAIN);
-    if let Some(state) = app.connection_state() {
-        let t = state.to_string();
-        let (style, t) = match state {
-            webrtc::peer_connection::peer_connection_state::RTCPeerConnectionState::Unspecified => {
-                (
-                    Style::new().black().on_gray().bold().italic(),
-                    "Unspecified".gray(),
-                )
-            }
-
-            webrtc::peer_connection::peer_connection_state::RTCPeerConnectionState::Connecting => (
-                Style::new().white().on_light_green().bold().italic(),
-                "Connecting".green(),
-            ),
-            webrtc::peer_connection::peer_connection_state::RTCPeerConnectionState::Connected => (
-                Style::new().white().on_green().bold().italic(),
-                "Connected".green(),
-            ),
-            RTCPeerConnectionState::Disconnected
-            | RTCPeerConnectionState::Failed
-            | RTCPeerConnectionState::Closed
-            | RTCPeerConnectionState::New => (
-                Style::new().white().on_red().bold().italic(),
-                t.to_string().red(),
-            ),
+    if view.show_qr {
+        let area = block.inner(layout[1]);
+        block.render(layout[1], frame.buffer_mut());
+        let lines = match view.qr_code(&app.qr_desc) {
+            _ if app.qr_desc.is_empty() => vec![Line::from("No description yet".gray())],
+            Some(qr) if qr_fits(qr, area) => qr_lines(qr),
+            Some(_) => vec![Line::from(
+                "Enlarge the terminal to show the QR code".gray(),
+            )],
+            None => vec![Line::from(
+                "Description is too large for a QR code, copy it instead".red(),
+            )],
         };
-        block = block.title((" Status: ".to_span() + t.bold() + " ".to_span()).centered());
+        Paragraph::new(lines)
+            .centered()
+            .render(area, frame.buffer_mut());
     } else {
-        block = block.title((" Status: ".to_span() + "Unknown ".gray().bold()).centered());
+        Paragraph::new(Line::from(format!("desc: {}", app.local_desc)))
+            .centered()
+            .block(block)
+            .render(layout[1], frame.buffer_mut());
     }
 
+    let mut block = Block::bordered().border_set(border::PLAIN);
+    let state = app.session();
+    let t = match state {
+        SessionState::Idle | SessionState::Stopped => state.to_string().gray(),
+        SessionState::AwaitingOffer | SessionState::Answered | SessionState::Connecting => {
+            state.to_string().yellow()
+        }
+        SessionState::Streaming => state.to_string().green(),
+        SessionState::Failed { .. } => state.to_string().red(),
+    };
+    block = block.title((" Status: ".to_span() + t.bold() + " ".to_span()).centered());
+
+    let info = app.stream_info();
+    let resampler = match info.map(|i| i.resampler) {
+        Some(Some(kind)) => kind.to_string().green(),
+        Some(None) => "none (48 kHz input)".gray(),
+        None => app.config().resampler.to_string().gray(),
+    };
+    let recording = match info.and_then(|i| i.recording.as_ref()) {
+        Some(path) => path.display().to_string().red(),
+        None => "off".gray(),
+    };
+    let now_playing = match &app.metadata().track {
+        Some(track) => track.to_string().into(),
+        None => "-".gray(),
+    };
+    let pairing = match app.pairing().pin() {
+        Some(pin) => pin.bold(),
+        None => "shared secret from config".gray(),
+    };
+    let failed = match app.failed_pairings().last() {
+        Some((addr, reason)) => {
+            format!("{} (last: {addr}, {reason})", app.failed_pairings().count()).red()
+        }
+        None => "none".gray(),
+    };
+    let peer = match (app.connection_state(), app.ice_state()) {
+        (Some(state), Some(ice)) => format!("{state} (ICE {ice})").into(),
+        (Some(state), None) => state.to_string().into(),
+        (None, _) => "-".gray(),
+    };
+    let mut lines = vec![
+        Line::from(vec!["Peer connection: ".into(), peer]),
+        Line::from(vec!["Pairing PIN: ".into(), pairing]),
+        Line::from(vec!["Rejected offers: ".into(), failed]),
+        Line::from(vec!["Resampler: ".into(), resampler]),
+        Line::from(vec!["Recording: ".into(), recording]),
+        Line::from(vec!["Now playing: ".into(), now_playing]),
+    ];
+    let reconnect = app.reconnect();
+    if reconnect.gave_up() {
+        lines.push(Line::from(vec![
+            "Reconnect: ".into(),
+            format!("gave up after {} attempts", reconnect.attempts()).red(),
+        ]));
+    } else if let Some(next) = reconnect.next_attempt_in() {
+        lines.push(Line::from(vec![
+            "Reconnect: ".into(),
+            format!(
+                "attempt {}/{MAX_ATTEMPTS}, next in {}s",
+                reconnect.attempts(),
+                next.as_secs()
+            )
+            .yellow(),
+        ]));
+    }
+    if let Some(notice) = app.notice() {
+        lines.push(Line::from(notice.to_string().yellow()));
+    }
+    if let Some(stats) = app.stats() {
+        lines.extend(stats_lines(stats));
+    }
+    if let Some(latency) = app.latency() {
+        lines.extend(latency_lines(&latency, &view.keymap));
+    }
     // ratatui::widgets::
-    Paragraph::new(Line::from(""))
+    Paragraph::new(lines)
         .block(block)
         .render(layout[0], frame.buffer_mut());
 }
-pub fn draw_left_panel(app: &mut App, frame: &mut Frame, layout: &Rc<[Rect]>) {
+/// Light border around the code in modules, scanners need some contrast around it.
+const QR_QUIET_ZONE: usize = 2;
+/// Whether the code and its quiet zone fit into `area`, two rows of modules per line.
+fn qr_fits(qr: &QrCode, area: Rect) -> bool {
+    let size = qr.size() as usize + 2 * QR_QUIET_ZONE;
+    size <= area.width as usize && size.div_ceil(2) <= area.height as usize
+}
+/// Two modules per cell using half blocks, drawn light on dark so it scans on dark terminals.
+fn qr_lines<'a>(qr: &QrCode) -> Vec<Line<'a>> {
+    let size = qr.size() as usize + 2 * QR_QUIET_ZONE;
+    // modules outside the code are light
+    let dark = |x: usize, y: usize| {
+        qr.get_module(
+            x as i32 - QR_QUIET_ZONE as i32,
+            y as i32 - QR_QUIET_ZONE as i32,
+        )
+    };
+    (0..size)
+        .step_by(2)
+        .map(|y| {
+            let row = (0..size)
+                .map(|x| match (dark(x, y), y + 1 < size && dark(x, y + 1)) {
+                    (false, false) if y + 1 < size => '█',
+                    (false, _) => '▀',
+                    (true, false) if y + 1 < size => '▄',
+                    (true, _) => ' ',
+                })
+                .collect::<String>();
+            Line::from(row.white().on_black())
+        })
+        .collect()
+}
+fn stats_lines<'a>(stats: &ConnectionStats) -> Vec<Line<'a>> {
+    let or_dash = |v: Option<String>| v.unwrap_or("-".to_string());
+    let loss = match (stats.fraction_lost, stats.packets_lost) {
+        (Some(fraction), Some(lost)) => Some(format!("{:.1}% ({lost} lost)", fraction * 100.)),
+        (Some(fraction), None) => Some(format!("{:.1}%", fraction * 100.)),
+        (None, Some(lost)) => Some(format!("{lost} lost")),
+        (None, None) => None,
+    };
+    vec![
+        Line::from(""),
+        Line::from(vec![
+            "Bitrate: ".into(),
+            or_dash(stats.bitrate.map(|b| format!("{:.1} kbit/s", b / 1000.))).bold(),
+        ]),
+        Line::from(vec![
+            "Packets sent: ".into(),
+            stats.packets_sent.to_string().bold(),
+        ]),
+        Line::from(vec!["Loss: ".into(), or_dash(loss).bold()]),
+        Line::from(vec![
+            "Jitter: ".into(),
+            or_dash(stats.jitter_ms.map(|j| format!("{j:.1} ms"))).bold(),
+        ]),
+        Line::from(vec![
+            "RTT: ".into(),
+            or_dash(stats.rtt_ms.map(|r| format!("{r:.0} ms"))).bold(),
+        ]),
+        Line::from(vec![
+            "ICE pair: ".into(),
+            or_dash(stats.candidate_pair.clone()).into(),
+        ]),
+        Line::from(vec![
+            "Transport: ".into(),
+            or_dash(stats.transport.clone()).into(),
+        ]),
+    ]
+}
+fn latency_lines<'a>(latency: &LatencyBreakdown, keymap: &Keymap) -> Vec<Line<'a>> {
+    let network = latency
+        .network_ms
+        .map(|n| format!("{n:.1} ms"))
+        .unwrap_or("-".to_string());
+    let click = match latency.click_round_trip {
+        Some(d) => format!("{} ms", d.as_millis()).bold(),
+        None => match keymap.keys(Action::LatencyClick).first() {
+            Some(key) => format!("press <{key}> with a cooperating receiver").gray(),
+            None => "needs a key for latency_click and a cooperating receiver".gray(),
+        },
+    };
+    vec![
+        Line::from(""),
+        Line::from(vec![
+            "Latency: ".into(),
+            format!("{:.1} ms", latency.total_ms()).bold(),
+        ]),
+        Line::from(format!(
+            "  capture {:.1} ms, resampler {:.1} ms, opus {:.1} ms, network {network}",
+            latency.capture_ms, latency.resampler_ms, latency.opus_ms
+        )),
+        Line::from(vec!["Click round trip: ".into(), click]),
+    ]
+}
+pub fn draw_left_panel(app: &App, view: &mut View, frame: &mut Frame, layout: &Rc<[Rect]>) {
     let mut block = Block::bordered()
         .title(Line::from(" Available devices ".bold()).centered())
         .border_set(border::PLAIN);
-    if *app.state() == Selected::Left {
-        let i = vec![
-            KeyInfo::new("Move up", KeyCode::Up),
-            KeyInfo::new("Select", KeyCode::Enter),
-            KeyInfo::new("Move down", KeyCode::Down),
-        ];
+    if view.focus == Selected::Left {
+        let i = hints(
+            &view.keymap,
+            &[
+                Action::MoveUp,
+                Action::SelectDevice,
+                Action::MoveDown,
+                Action::Help,
+            ],
+        );
         block = block
             .border_set(border::THICK)
             .title_bottom(instructions(&i).centered());
@@ -110,13 +300,93 @@ pub fn draw_left_panel(app: &mut App, frame: &mut Frame, layout: &Rc<[Rect]>) {
     .block(block)
     .highlight_symbol(">")
     .highlight_spacing(ratatui::widgets::HighlightSpacing::Always);
-    StatefulWidget::render(l, layout[0], frame.buffer_mut(), &mut app.list_state);
+    StatefulWidget::render(l, layout[0], frame.buffer_mut(), &mut view.list_state);
+}
+pub fn draw_log_panel(view: &View, frame: &mut Frame, area: Rect) {
+    let scroll = hints(
+        &view.keymap,
+        &[
+            Action::ScrollLogUp,
+            Action::ScrollLogDown,
+            Action::ToggleLog,
+        ],
+    );
+    let block = Block::bordered()
+        .title(Line::from(" Log ".bold()).centered())
+        .title_bottom(instructions(&scroll).centered())
+        .border_set(border::PLAIN);
+    let records = view.logs.records();
+    // newest record at the bottom unless scrolled up
+    let end = records.len().saturating_sub(view.log_scroll);
+    let start = end.saturating_sub(block.inner(area).height as usize);
+    let lines: Vec<Line> = records[start..end]
+        .iter()
+        .map(|record| {
+            let level = format!("{:<5}", record.level);
+            let level = match record.level {
+                Level::Error => level.red(),
+                Level::Warn => level.yellow(),
+                Level::Info => level.green(),
+                Level::Debug | Level::Trace => level.gray(),
+            };
+            Line::from(vec![
+                level.bold(),
+                format!(" {}: ", record.module).gray(),
+                record.message.clone().into(),
+            ])
+        })
+        .collect();
+    Paragraph::new(lines)
+        .block(block)
+        .render(area, frame.buffer_mut());
 }
-pub fn draw_popup(app: &mut App, frame: &mut Frame) {
-    let block = Block::bordered().title("Popup");
+
+/// Every action for the focused panel with all of its keys.
+pub fn draw_help(view: &View, frame: &mut Frame) {
+    let close = hints(&view.keymap, &[Action::Help]);
+    let block = Block::bordered()
+        .title(Line::from(" Help ".bold()).centered())
+        .title_bottom(instructions(&close).centered())
+        .border_set(border::THICK);
+    let mut lines = Vec::new();
+    for context in view.contexts() {
+        lines.push(Line::from(context.title().bold()));
+        for action in Action::ALL.into_iter().filter(|a| a.context() == context) {
+            let keys = match view.keymap.keys(action) {
+                [] => "unbound".gray(),
+                keys => keys
+                    .iter()
+                    .map(|key| format!("<{key}>"))
+                    .collect::<Vec<_>>()
+                    .join(" ")
+                    .blue()
+                    .bold(),
+            };
+            lines.push(Line::from(vec![
+                format!("  {:<20}", action.description()).into(),
+                keys,
+            ]));
+        }
+    }
+    let area = popup_area(frame.area(), 50, 70);
+    frame.render_widget(Clear, area);
+    Paragraph::new(lines)
+        .block(block)
+        .render(area, frame.buffer_mut());
+}
+
+pub fn draw_popup(app: &App, view: &View, frame: &mut Frame) {
+    let dismiss = hints(&view.keymap, &[Action::Dismiss]);
+    let block = Block::bordered()
+        .title(Line::from(" Error ".bold().red()).centered())
+        .title_bottom(instructions(&dismiss).centered())
+        .border_set(border::THICK);
     let area = popup_area(frame.area(), 60, 20);
     frame.render_widget(Clear, area); //this clears out the background
-    frame.render_widget(block, area);
+    Paragraph::new(app.error().unwrap_or_default().to_string())
+        .wrap(Wrap { trim: true })
+        .block(block)
+        .render(area, frame.buffer_mut());
 }
 fn popup_area(area: Rect, percent_x: u16, percent_y: u16) -> Rect {
     let vertical = Layout::vertical([Constraint::Percentage(percent_y)]).flex(Flex::Center);
@@ -128,16 +398,26 @@ fn popup_area(area: Rect, percent_x: u16, percent_y: u16) -> Rect {
 
 pub struct KeyInfo {
     info: String,
-    key: KeyCode,
+    key: Key,
 }
 impl KeyInfo {
-    pub fn new(info: &str, key: KeyCode) -> Self {
+    pub fn new(info: &str, key: Key) -> Self {
         Self {
             info: info.to_string(),
             key,
         }
     }
 }
+/// Hints for `actions` with their first key, unbound actions are left out.
+fn hints(keymap: &Keymap, actions: &[Action]) -> Vec<KeyInfo> {
+    actions
+        .iter()
+        .filter_map(|action| {
+            let key = keymap.keys(*action).first()?;
+            Some(KeyInfo::new(action.description(), *key))
+        })
+        .collect()
+}
 pub fn instructions<'a>(keys: &Vec<KeyInfo>) -> Line<'a> {
     let mut res = Vec::new();
     for (i, key) in keys.iter().enumerate() {